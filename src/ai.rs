@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use crate::{
+    arena::ArenaConfig,
+    ball::{Ball, BallKind},
+    barrier::WallFilter,
+    game::{GameState, GameplayStep},
+    player::{Player, PlayerAction, PlayerRadius},
+    rules::GameRules,
+    tick::TickApp,
+    util::{clamp, Side},
+    ArenaInfo, TIME_STEP,
+};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(ai_target_system.label(GameplayStep::Ai))
+                .with_system(
+                    ai_control_system
+                        .label(GameplayStep::Ai)
+                        .after(ai_target_system),
+                ),
+        );
+    }
+}
+
+/// Distance to the target under which the AI stops moving
+const AI_DEAD_ZONE: f32 = 8.0;
+/// Distance to the target above which the AI accelerates
+const AI_ACCELERATE_DISTANCE: f32 = 120.0;
+/// How far in the future (in seconds) ball trajectories are predicted
+const AI_PREDICTION_HORIZON: f32 = 3.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// Seconds between two updates of the AI target
+    pub fn reaction_delay(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.35,
+            Difficulty::Normal => 0.2,
+            Difficulty::Hard => 0.08,
+        }
+    }
+
+    /// Number of bounces followed when predicting a ball trajectory
+    pub fn prediction_depth(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 0,
+            Difficulty::Normal => 1,
+            Difficulty::Hard => 3,
+        }
+    }
+
+    /// Maximum distance between the predicted impact and the AI target
+    pub fn aim_error(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 60.0,
+            Difficulty::Normal => 30.0,
+            Difficulty::Hard => 8.0,
+        }
+    }
+}
+
+/// Drives a player by pressing its `PlayerAction`s
+#[derive(Component)]
+pub struct AiController {
+    pub difficulty: Difficulty,
+    reaction_timer: Timer,
+    /// Offset along the player side the AI is moving to
+    target: f32,
+    /// Own source of randomness, so that replacing a CPU player does not change
+    /// the random decisions of the rest of the game
+    rng: StdRng,
+}
+
+impl AiController {
+    pub fn new(difficulty: Difficulty, seed: u64) -> Self {
+        Self {
+            difficulty,
+            reaction_timer: Timer::from_seconds(difficulty.reaction_delay(), true),
+            target: 0.0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+/// Follow a ball going from `pos` at `vel`, bouncing off corners and barriers
+/// up to `depth` times, and return where and when it crosses `side`.
+#[allow(clippy::too_many_arguments)]
+pub fn predict_side_crossing(
+    rapier_context: &RapierContext,
+    is_obstacle: &dyn Fn(Entity) -> bool,
+    arena_info: &ArenaInfo,
+    side: Side,
+    mut pos: Vec2,
+    mut vel: Vec2,
+    ball_radius: f32,
+    depth: u32,
+) -> Option<(f32, f32)> {
+    let normal = side.normal();
+    let side_middle = arena_info.0.side_middle(side).truncate();
+    let shape = Collider::ball(ball_radius);
+
+    let mut time = 0.0;
+    let mut last_hit = None;
+
+    for _ in 0..=depth {
+        let remaining = AI_PREDICTION_HORIZON - time;
+        let filter_fn = |entity| is_obstacle(entity) && Some(entity) != last_hit;
+        let filter = QueryFilter::new().exclude_sensors().predicate(&filter_fn);
+        let hit = rapier_context.cast_shape(pos, 0.0, vel, &shape, remaining, filter);
+
+        // Time until the ball centre reaches the side line
+        let normal_speed = vel.dot(normal);
+        let line_toi = if normal_speed > 0.0 {
+            Some((side_middle - pos).dot(normal) / normal_speed)
+        } else {
+            None
+        };
+
+        match (line_toi, hit) {
+            (Some(line_toi), hit)
+                if line_toi >= 0.0
+                    && line_toi <= remaining
+                    && hit.map_or(true, |(_, toi)| line_toi < toi.toi) =>
+            {
+                let crossing = pos + vel * line_toi;
+                return Some((
+                    (crossing - side_middle).dot(side.tangent()),
+                    time + line_toi,
+                ));
+            }
+            (_, Some((entity, toi))) => {
+                // Bounce: mirror the velocity around the contact normal
+                pos += vel * toi.toi;
+                vel -= 2.0 * vel.dot(toi.normal1) * toi.normal1;
+                time += toi.toi;
+                last_hit = Some(entity);
+            }
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+fn ai_target_system(
+    rapier_context: Res<RapierContext>,
+    arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+    rules: Res<GameRules>,
+    mut ai_query: Query<(&Player, &PlayerRadius, &mut AiController)>,
+    ball_query: Query<(&Transform, &Velocity, &BallKind), With<Ball>>,
+    obstacle_query: Query<(), WallFilter>,
+) {
+    let is_obstacle = |entity| obstacle_query.contains(entity);
+    for (player, radius, mut ai) in ai_query.iter_mut() {
+        if !ai
+            .reaction_timer
+            .tick(Duration::from_secs_f32(TIME_STEP))
+            .just_finished()
+        {
+            continue;
+        }
+
+        // Defend against the ball reaching the side first, or go back to the middle
+        let threat = ball_query
+            .iter()
+            .filter_map(|(ball_tf, velocity, kind)| {
+                predict_side_crossing(
+                    &rapier_context,
+                    &is_obstacle,
+                    &arena_info,
+                    player.side,
+                    ball_tf.translation.truncate(),
+                    velocity.linvel,
+                    kind.radius(&rules.ball),
+                    ai.difficulty.prediction_depth(),
+                )
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let error = ai.difficulty.aim_error();
+        let bound =
+            arena_info.0.side_length(player.side) / 2.0 - arena_config.corner_radius - radius.0;
+
+        ai.target = match threat {
+            Some((offset, _)) => clamp(offset + ai.rng.gen_range(-error..=error), -bound, bound),
+            None => 0.0,
+        };
+    }
+}
+
+fn ai_control_system(
+    mut ai_query: Query<(
+        &Player,
+        &PlayerRadius,
+        &AiController,
+        &Transform,
+        &mut ActionState<PlayerAction>,
+    )>,
+    ball_query: Query<(&Transform, &Velocity, &BallKind), With<Ball>>,
+    arena_info: Res<ArenaInfo>,
+    rules: Res<GameRules>,
+) {
+    for (player, radius, ai, player_tf, mut action_state) in ai_query.iter_mut() {
+        let side_middle = arena_info.0.side_middle(player.side);
+        let offset = (player_tf.translation - side_middle)
+            .truncate()
+            .dot(player.side.tangent());
+        let distance = ai.target - offset;
+
+        set_pressed(
+            &mut action_state,
+            PlayerAction::MoveLeft,
+            distance < -AI_DEAD_ZONE,
+        );
+        set_pressed(
+            &mut action_state,
+            PlayerAction::MoveRight,
+            distance > AI_DEAD_ZONE,
+        );
+        set_pressed(
+            &mut action_state,
+            PlayerAction::Accelerate,
+            distance.abs() > AI_ACCELERATE_DISTANCE,
+        );
+
+        // Fire as soon as a ball coming towards the player is in energy range.
+        // Pulses go off on release, so the AI taps instead of charging.
+        let ball_incoming = ball_query.iter().any(|(ball_tf, velocity, kind)| {
+            let energy_range = radius.0 + rules.player.energy_radius + kind.radius(&rules.ball);
+            let vect = player_tf.translation.truncate() - ball_tf.translation.truncate();
+            vect.length() < energy_range && vect.dot(velocity.linvel) > 0.0
+        });
+        let tap = ball_incoming && !action_state.pressed(PlayerAction::Energy);
+
+        set_pressed(&mut action_state, PlayerAction::Energy, tap);
+    }
+}
+
+fn set_pressed(action_state: &mut ActionState<PlayerAction>, action: PlayerAction, pressed: bool) {
+    if pressed {
+        action_state.press(action);
+    } else {
+        action_state.release(action);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    game::GameState,
+    util::{list_assets, load_ron_asset, Side},
+    ArenaInfo, ARENA_MARGIN,
+};
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+
+/// Keeps `ArenaInfo` and the background in sync with the `ArenaConfig`
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ArenaConfig>();
+
+        let config = app.world.resource::<ArenaConfig>();
+        let arena_info = ArenaInfo::centered(config.width, config.height);
+        let background = ClearColor(config.background);
+
+        app.insert_resource(arena_info)
+            .insert_resource(background)
+            .add_system_to_stage(CoreStage::PreUpdate, arena_config_system);
+    }
+}
+
+/// Reloads the arena file whenever it changes on disk, restarting the current
+/// round with the new geometry. Needs the asset server, so windowed only.
+pub struct ArenaHotReloadPlugin;
+
+impl Plugin for ArenaHotReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<ArenaConfig>()
+            .init_asset_loader::<ArenaConfigLoader>()
+            .add_system(arena_file_load_system)
+            .add_system(arena_hot_reload_system.after(arena_file_load_system))
+            .add_system(arena_window_system);
+    }
+}
+
+pub const DEFAULT_ARENA_FILE: &str = "arenas/classic.arena.ron";
+const ARENA_EXTENSION: &str = ".arena.ron";
+
+/// Geometry and look of the arena, read from a `.arena.ron` file of the assets
+#[derive(Serialize, Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "6f1c1a52-8d4e-4f0b-9a57-3c2b8e0d7a41"]
+pub struct ArenaConfig {
+    pub width: f32,
+    pub height: f32,
+    pub corner_radius: f32,
+    pub barrier_thickness: f32,
+    /// Sides left open for a player, the others are closed by a barrier
+    pub goals: Vec<Side>,
+    /// Restitution of the corners and barriers
+    pub restitution: f32,
+    /// Friction of the corners and barriers
+    pub friction: f32,
+    pub background: Color,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        // Same arena as the default 850x850 window
+        Self {
+            width: 750.0,
+            height: 750.0,
+            corner_radius: 70.0,
+            barrier_thickness: 10.0,
+            goals: Side::ALL.to_vec(),
+            restitution: 1.0,
+            friction: 0.0,
+            background: Color::rgb(0.04, 0.04, 0.04),
+        }
+    }
+}
+
+impl ArenaConfig {
+    /// Read an arena file, `path` being relative to the assets folder
+    pub fn load(path: &str) -> Result<Self, String> {
+        let config: Self = load_ron_asset(path)?;
+        config
+            .validate()
+            .map_err(|err| format!("invalid arena {}: {}", path, err))?;
+        Ok(config)
+    }
+
+    /// Check that the arena can be built, its corners leaving room for the
+    /// sides
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = [
+            ("width", self.width),
+            ("height", self.height),
+            ("corner_radius", self.corner_radius),
+            ("barrier_thickness", self.barrier_thickness),
+        ];
+        let non_negative = [
+            ("restitution", self.restitution),
+            ("friction", self.friction),
+        ];
+
+        for (name, value) in positive.iter().chain(&non_negative) {
+            if !value.is_finite() {
+                return Err(format!("{} must be a finite number, not {}", name, value));
+            }
+        }
+        for (name, value) in positive {
+            if value <= 0.0 {
+                return Err(format!("{} must be positive, not {}", name, value));
+            }
+        }
+        for (name, value) in non_negative {
+            if value < 0.0 {
+                return Err(format!("{} must not be negative, not {}", name, value));
+            }
+        }
+        if 2.0 * self.corner_radius >= self.width.min(self.height) {
+            return Err(format!(
+                "corner_radius {} leaves no room for the sides of a {}x{} arena",
+                self.corner_radius, self.width, self.height
+            ));
+        }
+        Ok(())
+    }
+
+    /// Arena files of `assets/arenas`
+    pub fn files() -> Vec<String> {
+        list_assets("arenas", ARENA_EXTENSION)
+    }
+
+    /// Size of a window showing the whole arena
+    pub fn window_size(&self) -> Vec2 {
+        Vec2::new(
+            self.width + 2.0 * ARENA_MARGIN,
+            self.height + 2.0 * ARENA_MARGIN,
+        )
+    }
+}
+
+/// Path of the arena file, relative to the assets folder
+pub struct ArenaFile(pub String);
+
+struct ArenaHandle(Handle<ArenaConfig>);
+
+#[derive(Default)]
+struct ArenaConfigLoader;
+
+impl AssetLoader for ArenaConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let config: ArenaConfig = ron::de::from_bytes(bytes)?;
+            config.validate().map_err(bevy::asset::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(config));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["arena.ron"]
+    }
+}
+
+fn arena_config_system(
+    arena_config: Res<ArenaConfig>,
+    mut arena_info: ResMut<ArenaInfo>,
+    mut background: ResMut<ClearColor>,
+) {
+    if arena_config.is_changed() {
+        *arena_info = ArenaInfo::centered(arena_config.width, arena_config.height);
+        background.0 = arena_config.background;
+    }
+}
+
+/// Watch the arena file, again whenever another one is picked
+fn arena_file_load_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    arena_file: Res<ArenaFile>,
+) {
+    if arena_file.is_changed() {
+        commands.insert_resource(ArenaHandle(asset_server.load(&arena_file.0)));
+    }
+}
+
+fn arena_hot_reload_system(
+    mut events: EventReader<AssetEvent<ArenaConfig>>,
+    mut arena_config: ResMut<ArenaConfig>,
+    mut state: ResMut<State<GameState>>,
+    arenas: Res<Assets<ArenaConfig>>,
+    arena_handle: Option<Res<ArenaHandle>>,
+    mut pending: Local<Option<ArenaConfig>>,
+) {
+    if let Some(arena_handle) = arena_handle {
+        for event in events.iter() {
+            if let AssetEvent::Modified { handle } = event {
+                if *handle != arena_handle.0 {
+                    continue;
+                }
+                if let Some(config) = arenas.get(handle) {
+                    info!("Arena file reloaded");
+                    *pending = Some(config.clone());
+                }
+            }
+        }
+    }
+
+    let config = match pending.take() {
+        Some(config) => config,
+        None => return,
+    };
+
+    // Play the current round again in the new arena. The config is only
+    // swapped once the round restarts, so that it always matches the walls
+    // built, and kept for the next frame while another change is queued.
+    let restarted = match state.current() {
+        GameState::Countdown => state.restart(),
+        GameState::Playing | GameState::Paused => state.replace(GameState::Countdown),
+        _ => Ok(()),
+    };
+    if restarted.is_ok() {
+        *arena_config = config;
+    } else {
+        *pending = Some(config);
+    }
+}
+
+fn arena_window_system(arena_config: Res<ArenaConfig>, mut windows: ResMut<Windows>) {
+    if arena_config.is_changed() {
+        if let Some(window) = windows.get_primary_mut() {
+            let size = arena_config.window_size();
+            window.set_resolution(size.x, size.y);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_files_are_valid() {
+        let files = ArenaConfig::files();
+        assert!(!files.is_empty());
+        for file in files {
+            if let Err(err) = ArenaConfig::load(&file) {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_arena_is_rejected() {
+        let config = ArenaConfig {
+            height: 0.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = ArenaConfig {
+            corner_radius: -10.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,107 +1,574 @@
-use std::f32::consts::PI;
+use std::{collections::VecDeque, f32::consts::PI, time::Duration};
 
-use crate::{corner::CORNER_RADIUS, util::Rectangle, ArenaInfo};
-use bevy::{prelude::*, time::FixedTimestep};
+use crate::{
+    arena::ArenaConfig,
+    barrier::Barrier,
+    corner::{Corner, CornerAnimation, CORNER_GROUP},
+    game::{GameRng, GameState, GameplayStep, RoundEntity},
+    pacing::Pacing,
+    player::{player_color, Player, PlayerRadius, PlayerVelocity},
+    powerup::{FreeBallFilter, PowerUp},
+    rules::{BallKindRules, BallRules, DeflectionModel, DeflectionRules, GameRules},
+    score::GoalScored,
+    tick::TickApp,
+    util::{clamp, Rectangle, Side},
+    ArenaInfo, GoalSides, TIME_STEP,
+};
+use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 pub struct BallPlugin;
 
 impl Plugin for BallPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
-            SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(1.0))
-                .with_system(ball_spawn_system),
+        app.add_tick_system_set(
+            SystemSet::on_enter(GameState::Countdown).with_system(ball_reset_system),
         )
-        .add_system(ball_despawn_system)
-        .add_system(ball_speed_control_system)
-        .insert_resource(BallCounter(0));
+        .add_tick_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(
+                    ball_population_system
+                        .label(GameplayStep::Ball)
+                        .after(GameplayStep::Pacing),
+                )
+                .with_system(
+                    ball_collision_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_population_system),
+                )
+                .with_system(
+                    ball_touch_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_collision_system),
+                )
+                .with_system(
+                    ball_energy_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_touch_system),
+                )
+                .with_system(
+                    ball_deflection_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_energy_system),
+                )
+                .with_system(
+                    ball_explosion_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_deflection_system),
+                )
+                .with_system(
+                    ball_curve_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_explosion_system),
+                )
+                .with_system(
+                    ball_trajectory_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_curve_system),
+                )
+                .with_system(
+                    ball_speed_control_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_trajectory_system),
+                )
+                .with_system(
+                    ball_spawn_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_speed_control_system),
+                )
+                .with_system(
+                    ball_despawn_system
+                        .label(GameplayStep::Ball)
+                        .after(ball_spawn_system),
+                ),
+        )
+        .add_tick_event::<BallHitPlayer>()
+        .add_tick_event::<BallHitCorner>()
+        .add_tick_event::<BallHitBarrier>()
+        .add_tick_event::<BallHitBall>()
+        .add_tick_event::<BallHitPowerUp>()
+        .init_resource::<BallPopulation>()
+        .init_resource::<BallSpawner>();
     }
 }
 
-pub const MAX_BALL_COUNT: u32 = 5;
-pub const BALL_SPEED: f32 = 400.0;
-pub const BALL_RADIUS: f32 = 20.0;
-
 #[derive(Component)]
 pub struct Ball;
 
-#[derive(Component)]
+/// Sent when a ball starts touching a player
+pub struct BallHitPlayer {
+    pub ball: Entity,
+    pub player: Entity,
+}
+
+/// Sent when a ball starts touching a corner
+pub struct BallHitCorner {
+    pub ball: Entity,
+    pub corner: Entity,
+}
+
+/// Sent when a ball starts touching a barrier
+pub struct BallHitBarrier {
+    pub ball: Entity,
+    pub barrier: Entity,
+}
+
+/// Sent once when two balls start touching each other
+pub struct BallHitBall {
+    pub ball: Entity,
+    pub other: Entity,
+}
+
+/// Sent when a ball starts touching a power-up pickup
+pub struct BallHitPowerUp {
+    pub ball: Entity,
+    pub power_up: Entity,
+}
+
+const BALL_COLOR: Color = Color::GRAY;
+/// Angle (in degrees) between the balls of a split
+const SPLIT_ANGLE: f32 = 15.0;
+
+/// Kind of a ball, changing its size, speed and how it bounces
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BallKind {
+    Normal,
+    /// Big and slow, energy pulses energize it without turning it
+    Heavy,
+    SmallFast,
+    /// Splits into several balls when hitting a player, then becomes normal
+    Explosive,
+    /// Keeps turning the same way, picked when spawned
+    Curving {
+        clockwise: bool,
+    },
+    /// Goes through the corners
+    Ghost,
+}
+
+impl BallKind {
+    /// Pick a kind with a chance proportional to its spawn weight
+    pub fn random(rng: &mut impl Rng, rules: &BallKindRules) -> Self {
+        let weights = [
+            rules.normal_weight,
+            rules.heavy_weight,
+            rules.small_fast_weight,
+            rules.explosive_weight,
+            rules.curving_weight,
+            rules.ghost_weight,
+        ];
+        let total: u32 = weights.iter().sum();
+        if total == 0 {
+            return BallKind::Normal;
+        }
+
+        let mut pick = rng.gen_range(0..total);
+        let idx = weights
+            .iter()
+            .position(|weight| {
+                if pick < *weight {
+                    true
+                } else {
+                    pick -= weight;
+                    false
+                }
+            })
+            .unwrap_or(0);
+
+        match idx {
+            1 => BallKind::Heavy,
+            2 => BallKind::SmallFast,
+            3 => BallKind::Explosive,
+            4 => BallKind::Curving {
+                clockwise: rng.gen(),
+            },
+            5 => BallKind::Ghost,
+            _ => BallKind::Normal,
+        }
+    }
+
+    pub fn radius(&self, rules: &BallRules) -> f32 {
+        match self {
+            BallKind::Heavy => rules.radius * rules.kinds.heavy_radius_factor,
+            BallKind::SmallFast => rules.radius * rules.kinds.small_radius_factor,
+            _ => rules.radius,
+        }
+    }
+
+    pub fn speed_factor(&self, rules: &BallRules) -> f32 {
+        match self {
+            BallKind::Heavy => rules.kinds.heavy_speed_factor,
+            BallKind::SmallFast => rules.kinds.small_speed_factor,
+            _ => 1.0,
+        }
+    }
+
+    /// Look of a ball of this kind filled with `color`
+    fn draw_mode(&self, mut color: Color) -> DrawMode {
+        let outlined = |color: Color, outline: Color, width: f32| DrawMode::Outlined {
+            fill_mode: bevy_prototype_lyon::prelude::FillMode::color(color),
+            outline_mode: StrokeMode::new(outline, width),
+        };
+
+        match self {
+            BallKind::Normal | BallKind::SmallFast => {
+                DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(color))
+            }
+            BallKind::Heavy => outlined(color, Color::DARK_GRAY, 5.0),
+            BallKind::Explosive => outlined(color, Color::RED, 3.0),
+            BallKind::Curving { .. } => outlined(color, Color::YELLOW, 2.0),
+            BallKind::Ghost => DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(
+                *color.set_a(0.35),
+            )),
+        }
+    }
+}
+
+/// Energy given to a ball by the energy ring of a player
+#[derive(Clone, Copy, Debug)]
+pub struct BallEnergy {
+    pub owner: Entity,
+    pub owner_side: Side,
+    /// Goes from 1 when energized down to 0, scales the speed boost
+    pub level: f32,
+    /// Extra speed factor given by a charged pulse
+    pub boost: f32,
+    /// Bounces on corners and barriers left before the energy is lost
+    pub bounces_left: u32,
+}
+
+#[derive(Component, Clone, Copy, Default)]
 pub struct BallState {
-    has_energy: bool,
+    pub energy: Option<BallEnergy>,
+    /// Last player who hit the ball or energized it
+    pub last_touch: Option<Entity>,
+    /// Part of the speed added by the pacing of the round
+    pub speedup: f32,
+    /// Part of the speed taken away by a slow motion effect
+    pub slowdown: f32,
 }
 
 impl BallState {
-    pub fn energize(self: &mut Self) {
-        self.has_energy = true;
+    pub fn energize(&mut self, owner: Entity, owner_side: Side, boost: f32, rules: &BallRules) {
+        self.last_touch = Some(owner);
+        self.energy = Some(BallEnergy {
+            owner,
+            owner_side,
+            level: 1.0,
+            boost,
+            bounces_left: rules.energy_bounces,
+        });
+    }
+
+    /// Side of the player who last energized the ball, if it still has energy
+    pub fn owner_side(&self) -> Option<Side> {
+        self.energy.map(|energy| energy.owner_side)
+    }
+
+    pub fn speed(&self, rules: &BallRules) -> f32 {
+        let speed = match self.energy {
+            Some(energy) => {
+                let factor = rules.energized_speed_factor * energy.boost;
+                rules.speed * (1.0 + (factor - 1.0) * energy.level)
+            }
+            None => rules.speed,
+        };
+        speed * (1.0 + self.speedup) * (1.0 - self.slowdown)
+    }
+
+    /// Gray at rest, shifting to the owner colour with the energy level
+    fn color(&self) -> Color {
+        match self.energy {
+            Some(energy) => {
+                let owner = player_color(energy.owner_side);
+                let t = energy.level;
+                Color::rgb(
+                    BALL_COLOR.r() + (owner.r() - BALL_COLOR.r()) * t,
+                    BALL_COLOR.g() + (owner.g() - BALL_COLOR.g()) * t,
+                    BALL_COLOR.b() + (owner.b() - BALL_COLOR.b()) * t,
+                )
+            }
+            None => BALL_COLOR,
+        }
     }
 }
 
-impl Default for BallState {
-    fn default() -> Self {
-        Self { has_energy: false }
+/// Last walls a ball bounced off since it was last touched by a player
+#[derive(Component, Default)]
+pub struct BallTrajectory {
+    wall_contacts: VecDeque<Entity>,
+}
+
+impl BallTrajectory {
+    /// Whether the last `bounces` contacts all went back and forth between
+    /// the same two walls at most
+    pub fn is_looping(&self, bounces: u32) -> bool {
+        if bounces == 0 || self.wall_contacts.len() < bounces as usize {
+            return false;
+        }
+
+        let first = self.wall_contacts[0];
+        let other = self.wall_contacts.iter().find(|wall| **wall != first);
+        self.wall_contacts
+            .iter()
+            .all(|wall| *wall == first || Some(wall) == other)
     }
 }
 
+/// Balls in the arena, counted at the start of each tick, and how many are
+/// allowed at the moment
+#[derive(Default)]
+pub struct BallPopulation {
+    pub current: u32,
+    pub max: u32,
+}
+
+/// Ball announced at a corner, launched once its lead time is over
+struct PendingSpawn {
+    kind: BallKind,
+    position: Vec2,
+    direction: Vec2,
+    corner: Option<Entity>,
+    telegraph: Entity,
+    lead: Timer,
+}
+
+#[derive(Default)]
+struct BallSpawner {
+    timer: Timer,
+    /// Balls allowed by the waves so far, before pacing
+    wave_count: u32,
+    /// Runs between two increases of `wave_count`
+    wave_timer: Timer,
+    pending: Option<PendingSpawn>,
+}
+
+/// Arrow showing where the next ball comes from and where it goes
 #[derive(Component)]
-struct BallCounter(u32);
+struct SpawnTelegraph;
 
+const TELEGRAPH_LENGTH: f32 = 60.0;
+const TELEGRAPH_HEAD: f32 = 12.0;
+/// Seconds between two blinks of the telegraph
+const TELEGRAPH_BLINK: f32 = 0.15;
+
+fn ball_reset_system(
+    mut population: ResMut<BallPopulation>,
+    mut ball_spawner: ResMut<BallSpawner>,
+    rules: Res<GameRules>,
+) {
+    let rules = &rules.ball;
+    let wave_count = if rules.wave_interval > 0.0 {
+        rules.initial_count.min(rules.max_count)
+    } else {
+        rules.max_count
+    };
+    *population = BallPopulation {
+        current: 0,
+        max: wave_count,
+    };
+    *ball_spawner = BallSpawner {
+        timer: Timer::from_seconds(rules.spawn_interval, true),
+        wave_count,
+        wave_timer: Timer::from_seconds(rules.wave_interval, true),
+        pending: None,
+    };
+}
+
+/// Count the balls and let one more in at every wave, and more as the round
+/// speeds up
+fn ball_population_system(
+    mut population: ResMut<BallPopulation>,
+    mut ball_spawner: ResMut<BallSpawner>,
+    ball_query: Query<(), With<Ball>>,
+    pacing: Res<Pacing>,
+    rules: Res<GameRules>,
+) {
+    population.current = ball_query.iter().count() as u32;
+
+    if rules.ball.wave_interval > 0.0
+        && ball_spawner
+            .wave_timer
+            .tick(Duration::from_secs_f32(TIME_STEP))
+            .just_finished()
+    {
+        ball_spawner.wave_count = (ball_spawner.wave_count + 1).min(rules.ball.max_count);
+    }
+    population.max = ball_spawner.wave_count + pacing.extra_balls;
+}
+
+/// Announce a ball at a corner every spawn interval, and launch it once the
+/// lead time is over
+#[allow(clippy::too_many_arguments)]
 fn ball_spawn_system(
     mut commands: Commands,
-    mut ball_counter: ResMut<BallCounter>,
-    time: Res<Time>,
+    mut ball_spawner: ResMut<BallSpawner>,
+    mut rng: ResMut<GameRng>,
+    population: Res<BallPopulation>,
+    pacing: Res<Pacing>,
+    mut telegraph_query: Query<&mut Visibility, With<SpawnTelegraph>>,
+    corner_query: Query<(Entity, &Corner)>,
     arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+    rules: Res<GameRules>,
 ) {
-    if ball_counter.0 == MAX_BALL_COUNT {
-        return;
+    let rules = &rules.ball;
+    let delta = Duration::from_secs_f32(TIME_STEP);
+
+    if ball_spawner
+        .timer
+        .tick(delta.mul_f32(pacing.spawn_rate))
+        .just_finished()
+        && ball_spawner.pending.is_none()
+        && population.current < population.max
+    {
+        let pending = plan_spawn(
+            &mut commands,
+            &mut rng,
+            &corner_query,
+            &arena_info,
+            &arena_config,
+            rules,
+        );
+        ball_spawner.pending = Some(pending);
     }
 
-    let shape = shapes::Circle {
-        radius: BALL_RADIUS,
-        ..Default::default()
+    let pending = match &mut ball_spawner.pending {
+        Some(pending) => pending,
+        None => return,
     };
 
+    if !pending.lead.tick(delta).finished() {
+        if let Ok(mut visibility) = telegraph_query.get_mut(pending.telegraph) {
+            visibility.is_visible = (pending.lead.elapsed_secs() / TELEGRAPH_BLINK) as u32 % 2 == 0;
+        }
+        return;
+    }
+
+    spawn_ball(
+        &mut commands,
+        rules,
+        pending.kind,
+        pending.position,
+        pending.direction * rules.speed * pacing.speed * pending.kind.speed_factor(rules),
+        BallState {
+            speedup: pacing.speed - 1.0,
+            ..Default::default()
+        },
+    );
+    commands.entity(pending.telegraph).despawn();
+    if let Some(corner) = pending.corner {
+        commands.entity(corner).insert(CornerAnimation::eject());
+    }
+
+    ball_spawner.pending = None;
+}
+
+/// Choose the corner, direction and kind of the next ball, and start
+/// announcing it
+fn plan_spawn(
+    commands: &mut Commands,
+    rng: &mut GameRng,
+    corner_query: &Query<(Entity, &Corner)>,
+    arena_info: &ArenaInfo,
+    arena_config: &ArenaConfig,
+    rules: &BallRules,
+) -> PendingSpawn {
+    // In the order of the corner indices
     let spawn_points = [
-        (arena_info.0.top_left(), -45.0 as f32),
-        (arena_info.0.top_right(), -135.0 as f32),
-        (arena_info.0.bottom_left(), 45.0 as f32),
-        (arena_info.0.bottom_right(), 135.0 as f32),
+        (arena_info.0.top_left(), -45.0_f32),
+        (arena_info.0.top_right(), -135.0_f32),
+        (arena_info.0.bottom_left(), 45.0_f32),
+        (arena_info.0.bottom_right(), 135.0_f32),
     ];
 
-    let mut rng = thread_rng();
-    let spawn_idx = rng.gen_range(0..4);
+    let spawn_idx = rng.0.gen_range(0..4);
 
-    let (spawn_point, base_angle) = spawn_points[spawn_idx];
+    let (corner_point, base_angle) = spawn_points[spawn_idx];
 
-    let initial_angle = rng.gen_range(base_angle - 30.0..base_angle + 30.0);
+    let spread = rules.spawn_spread;
+    let initial_angle = rng.0.gen_range(base_angle - spread..=base_angle + spread);
     let initial_angle = initial_angle / 180.0 * PI;
-    let velocity_x = initial_angle.cos() * BALL_SPEED;
-    let velocity_y = initial_angle.sin() * BALL_SPEED;
-
-    let spawn_point = spawn_point
-        + Vec3 {
-            x: initial_angle.cos() * (CORNER_RADIUS + BALL_RADIUS),
-            y: initial_angle.sin() * (CORNER_RADIUS + BALL_RADIUS),
-            z: 0.0,
-        };
+    let direction = Vec2::from_angle(initial_angle);
+
+    let kind = BallKind::random(&mut rng.0, &rules.kinds);
+    let spawn_distance = arena_config.corner_radius + kind.radius(rules);
+    let position = corner_point.truncate() + direction * spawn_distance;
+
+    let corner = corner_query
+        .iter()
+        .find(|(_, corner)| corner.index == spawn_idx)
+        .map(|(corner, _)| corner);
+    if let Some(corner) = corner {
+        commands.entity(corner).insert(CornerAnimation::warning());
+    }
 
-    commands
+    let mut arrow = PathBuilder::new();
+    arrow.move_to(Vec2::ZERO);
+    arrow.line_to(Vec2::new(TELEGRAPH_LENGTH, 0.0));
+    arrow.move_to(Vec2::new(TELEGRAPH_LENGTH - TELEGRAPH_HEAD, TELEGRAPH_HEAD));
+    arrow.line_to(Vec2::new(TELEGRAPH_LENGTH, 0.0));
+    arrow.line_to(Vec2::new(
+        TELEGRAPH_LENGTH - TELEGRAPH_HEAD,
+        -TELEGRAPH_HEAD,
+    ));
+
+    let telegraph = commands
         .spawn_bundle(GeometryBuilder::build_as(
-            &shape,
-            DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(Color::GRAY)),
+            &arrow.build(),
+            DrawMode::Stroke(StrokeMode::new(Color::WHITE, 3.0)),
             Transform {
-                translation: Vec3 {
-                    x: spawn_point.x,
-                    y: spawn_point.y,
-                    z: 10.0,
-                },
+                translation: position.extend(10.0),
+                rotation: Quat::from_rotation_z(initial_angle),
                 ..Default::default()
             },
         ))
+        .insert(SpawnTelegraph)
+        .insert(RoundEntity)
+        .id();
+
+    PendingSpawn {
+        kind,
+        position,
+        direction,
+        corner,
+        telegraph,
+        lead: Timer::from_seconds(rules.spawn_lead_time, false),
+    }
+}
+
+/// Spawn a ball at `position`
+pub fn spawn_ball(
+    commands: &mut Commands,
+    rules: &BallRules,
+    kind: BallKind,
+    position: Vec2,
+    velocity: Vec2,
+    state: BallState,
+) -> Entity {
+    let radius = kind.radius(rules);
+    let shape = shapes::Circle {
+        radius,
+        ..Default::default()
+    };
+
+    let mut ball_commands = commands.spawn_bundle(GeometryBuilder::build_as(
+        &shape,
+        kind.draw_mode(state.color()),
+        Transform {
+            translation: position.extend(10.0),
+            ..Default::default()
+        },
+    ));
+
+    ball_commands
         .insert(Ball)
-        .insert(BallState::default())
+        .insert(kind)
+        .insert(RoundEntity)
+        .insert(state)
+        .insert(BallTrajectory::default())
         .insert(RigidBody::Dynamic)
         .insert(Restitution {
             coefficient: 1.0,
@@ -112,77 +579,562 @@ fn ball_spawn_system(
             combine_rule: CoefficientCombineRule::Min,
         })
         .insert(LockedAxes::ROTATION_LOCKED)
-        .insert(Velocity::linear(Vec2 {
-            x: velocity_x,
-            y: velocity_y,
-        }))
-        .insert(Collider::ball(BALL_RADIUS))
+        .insert(Velocity::linear(velocity))
+        .insert(Collider::ball(radius))
         .insert(ActiveEvents::COLLISION_EVENTS)
         .insert(Ccd::enabled());
 
-    ball_counter.0 += 1;
+    match kind {
+        BallKind::Heavy => {
+            ball_commands.insert(ColliderMassProperties::Density(rules.kinds.heavy_density));
+        }
+        BallKind::Ghost => {
+            ball_commands.insert(CollisionGroups {
+                memberships: u32::MAX,
+                filters: !CORNER_GROUP,
+            });
+        }
+        _ => {}
+    }
+
+    ball_commands.id()
 }
 
-fn is_out_of_bound(pos: &Vec3, arena_info: &Res<ArenaInfo>) -> bool {
-    return pos.x < arena_info.0.left() - CORNER_RADIUS
-        || pos.x > arena_info.0.right() + CORNER_RADIUS
-        || pos.y < arena_info.0.bottom() - CORNER_RADIUS
-        || pos.y > arena_info.0.top() + CORNER_RADIUS;
+/// Add `count` normal balls next to a ball, fanning out from its direction
+pub fn split_ball(
+    commands: &mut Commands,
+    rules: &BallRules,
+    ball_state: &BallState,
+    position: Vec2,
+    velocity: Vec2,
+    count: u32,
+) {
+    let direction = velocity.normalize_or_zero();
+    let spacing = 1.1 * (rules.max_radius() + rules.radius);
+
+    for idx in 1..=count {
+        // Alternate on each side of the ball, further away every two balls,
+        // slightly ahead so as not to overlap whatever the ball just hit
+        let rank = ((idx + 1) / 2) as f32;
+        let sign = if idx % 2 == 0 { -1.0 } else { 1.0 };
+        let offset = (direction.perp() * sign + direction) * rank * spacing;
+
+        let angle = (SPLIT_ANGLE * rank * sign).to_radians();
+        spawn_ball(
+            commands,
+            rules,
+            BallKind::Normal,
+            position + offset,
+            Vec2::from_angle(angle).rotate(velocity),
+            *ball_state,
+        );
+    }
 }
 
+fn is_out_of_bound(pos: &Vec3, arena_info: &ArenaInfo, margin: f32) -> bool {
+    pos.x < arena_info.0.left() - margin
+        || pos.x > arena_info.0.right() + margin
+        || pos.y < arena_info.0.bottom() - margin
+        || pos.y > arena_info.0.top() + margin
+}
+
+/// Remove the balls that went into a goal or out of the arena
 fn ball_despawn_system(
     mut commands: Commands,
-    mut ball_counter: ResMut<BallCounter>,
-    mut query: Query<(Entity, &Transform)>,
+    mut goal_events: EventReader<GoalScored>,
+    query: Query<(Entity, &Transform), With<Ball>>,
     arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
 ) {
-    for (entity, transform) in query.iter_mut() {
-        if is_out_of_bound(&transform.translation, &arena_info) {
+    let mut despawned: Vec<Entity> = goal_events.iter().map(|goal| goal.ball).collect();
+    despawned.extend(
+        query
+            .iter()
+            .filter(|(_, transform)| {
+                is_out_of_bound(
+                    &transform.translation,
+                    &arena_info,
+                    arena_config.corner_radius,
+                )
+            })
+            .map(|(entity, _)| entity),
+    );
+
+    // A ball may cross two goal zones at once, or leave the arena as it scores
+    despawned.sort();
+    despawned.dedup();
+    for entity in despawned {
+        if query.contains(entity) {
             commands.entity(entity).despawn();
-            ball_counter.0 -= 1;
         }
     }
 }
 
-pub fn ball_update_speed(vect: Vec2, ball_state: &BallState, velocity: &mut Velocity) {
+/// Speed of a ball of `kind`, with its energy and slowdown
+pub fn ball_speed(ball_state: &BallState, kind: &BallKind, rules: &BallRules) -> f32 {
+    ball_state.speed(rules) * kind.speed_factor(rules)
+}
+
+pub fn ball_update_speed(
+    vect: Vec2,
+    ball_state: &BallState,
+    kind: &BallKind,
+    rules: &BallRules,
+    velocity: &mut Velocity,
+) {
     // ensure constant speed
-    let speed = if ball_state.has_energy {
-        BALL_SPEED * 2.0
-    } else {
-        BALL_SPEED
-    };
-    let v = vect.normalize() * speed;
+    let v = vect.normalize() * ball_speed(ball_state, kind, rules);
     *velocity = Velocity::linear(v);
 }
 
-fn ball_speed_control_system(mut query: Query<(&BallState, &mut Velocity), With<Ball>>) {
-    for (ball_state, mut velocity) in query.iter_mut() {
+/// Remember the last player each ball bounced off
+fn ball_touch_system(
+    mut player_hits: EventReader<BallHitPlayer>,
+    mut ball_query: Query<&mut BallState, With<Ball>>,
+) {
+    for hit in player_hits.iter() {
+        if let Ok(mut ball_state) = ball_query.get_mut(hit.ball) {
+            ball_state.last_touch = Some(hit.player);
+        }
+    }
+}
+
+/// Drain the energy of the balls over time and on every bounce off a corner or
+/// a barrier
+fn ball_energy_system(
+    mut corner_hits: EventReader<BallHitCorner>,
+    mut barrier_hits: EventReader<BallHitBarrier>,
+    mut ball_query: Query<(&mut BallState, &BallKind, &mut DrawMode), With<Ball>>,
+    rules: Res<GameRules>,
+) {
+    for (ball_id, _) in wall_hits(&mut corner_hits, &mut barrier_hits) {
+        if let Ok((mut ball_state, _, _)) = ball_query.get_mut(ball_id) {
+            if let Some(energy) = &mut ball_state.energy {
+                energy.bounces_left = energy.bounces_left.saturating_sub(1);
+            }
+        }
+    }
+
+    let decay = TIME_STEP / rules.ball.energy_duration;
+    for (mut ball_state, kind, mut draw_mode) in ball_query.iter_mut() {
+        let energy = match &mut ball_state.energy {
+            Some(energy) => energy,
+            None => continue,
+        };
+
+        energy.level -= decay;
+        if energy.level <= 0.0 || energy.bounces_left == 0 {
+            ball_state.energy = None;
+        }
+
+        *draw_mode = kind.draw_mode(ball_state.color());
+    }
+}
+
+/// Velocity of a ball going at `ball_speed` bouncing off the player of `side`,
+/// from where it hits the player (-1 on its left edge to 1 on its right edge)
+/// and how fast the player moves. The speed is normalized again afterwards.
+pub fn deflect(
+    side: Side,
+    offset: f32,
+    player_velocity: f32,
+    ball_speed: f32,
+    rules: &DeflectionRules,
+) -> Vec2 {
+    let angle = (clamp(offset, -1.0, 1.0) * rules.max_angle).to_radians();
+    let tangent = side.tangent();
+
+    // Straight into the arena from the centre, turning towards the player
+    // right when hit on its right
+    let direction = -side.normal() * angle.cos() + tangent * angle.sin();
+    direction * ball_speed + tangent * player_velocity * rules.english
+}
+
+fn ball_deflection_system(
+    mut player_hits: EventReader<BallHitPlayer>,
+    mut ball_query: Query<(&BallState, &BallKind, &Transform, &mut Velocity), With<Ball>>,
+    player_query: Query<(&Player, &PlayerRadius, &PlayerVelocity, &Transform)>,
+    rules: Res<GameRules>,
+) {
+    if rules.deflection.model == DeflectionModel::Physics {
+        return;
+    }
+
+    for hit in player_hits.iter() {
+        let (player, radius, player_velocity, player_tf) = match player_query.get(hit.player) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let (ball_state, kind, ball_tf, mut velocity) = match ball_query.get_mut(hit.ball) {
+            Ok(ball) => ball,
+            Err(_) => continue,
+        };
+
+        let reach = radius.0 + kind.radius(&rules.ball);
+        let offset = (ball_tf.translation - player_tf.translation)
+            .truncate()
+            .dot(player.side.tangent())
+            / reach;
+        let deflected = deflect(
+            player.side,
+            offset,
+            player_velocity.0,
+            ball_speed(ball_state, kind, &rules.ball),
+            &rules.deflection,
+        );
+        ball_update_speed(deflected, ball_state, kind, &rules.ball, &mut velocity);
+    }
+}
+
+/// Split the explosive balls hitting a player, they carry on as normal balls
+fn ball_explosion_system(
+    mut commands: Commands,
+    mut player_hits: EventReader<BallHitPlayer>,
+    mut ball_query: Query<
+        (
+            &BallState,
+            &mut BallKind,
+            &Transform,
+            &Velocity,
+            &mut DrawMode,
+        ),
+        With<Ball>,
+    >,
+    rules: Res<GameRules>,
+) {
+    for hit in player_hits.iter() {
+        let (ball_state, mut kind, ball_tf, velocity, mut draw_mode) =
+            match ball_query.get_mut(hit.ball) {
+                Ok(ball) => ball,
+                Err(_) => continue,
+            };
+        if *kind != BallKind::Explosive {
+            continue;
+        }
+
+        *kind = BallKind::Normal;
+        *draw_mode = kind.draw_mode(ball_state.color());
+
+        split_ball(
+            &mut commands,
+            &rules.ball,
+            ball_state,
+            ball_tf.translation.truncate(),
+            velocity.linvel,
+            rules.ball.kinds.explosive_count,
+        );
+    }
+}
+
+/// Keep turning the curving balls
+fn ball_curve_system(mut ball_query: Query<(&BallKind, &mut Velocity)>, rules: Res<GameRules>) {
+    let turn = (rules.ball.kinds.curve_rate * TIME_STEP).to_radians();
+    for (kind, mut velocity) in ball_query.iter_mut() {
+        if let BallKind::Curving { clockwise } = kind {
+            let angle = if *clockwise { -turn } else { turn };
+            velocity.linvel = Vec2::from_angle(angle).rotate(velocity.linvel);
+        }
+    }
+}
+
+/// Turn `velocity` away from running along the barrier of `wall_side` when it
+/// makes less than `min_angle` (in radians) with it
+pub fn guard_wall_angle(velocity: Vec2, wall_side: Side, min_angle: f32) -> Vec2 {
+    let tangent = wall_side.tangent();
+    let normal = wall_side.normal();
+    let along = velocity.dot(tangent);
+    let across = velocity.dot(normal);
+
+    if velocity == Vec2::ZERO || across.abs().atan2(along.abs()) >= min_angle {
+        return velocity;
+    }
+
+    // Keep going the same way along the wall, leaving it if exactly parallel
+    let along_sign = if along < 0.0 { -1.0 } else { 1.0 };
+    let across_sign = if across > 0.0 { 1.0 } else { -1.0 };
+    (tangent * along_sign * min_angle.cos() + normal * across_sign * min_angle.sin())
+        * velocity.length()
+}
+
+/// Keep balls from getting trapped sliding along a barrier or bouncing
+/// between the same two walls
+fn ball_trajectory_system(
+    mut player_hits: EventReader<BallHitPlayer>,
+    mut corner_hits: EventReader<BallHitCorner>,
+    mut barrier_hits: EventReader<BallHitBarrier>,
+    mut ball_query: Query<(&mut BallTrajectory, &mut Velocity), With<Ball>>,
+    barrier_query: Query<&Barrier>,
+    rules: Res<GameRules>,
+) {
+    for hit in player_hits.iter() {
+        if let Ok((mut trajectory, _)) = ball_query.get_mut(hit.ball) {
+            trajectory.wall_contacts.clear();
+        }
+    }
+
+    let barrier_hits: Vec<&BallHitBarrier> = barrier_hits.iter().collect();
+    let wall_hits = corner_hits
+        .iter()
+        .map(|hit| (hit.ball, hit.corner))
+        .chain(barrier_hits.iter().map(|hit| (hit.ball, hit.barrier)));
+
+    let loop_bounces = rules.ball.loop_bounces as usize;
+    for (ball_id, wall_id) in wall_hits {
+        if let Ok((mut trajectory, _)) = ball_query.get_mut(ball_id) {
+            trajectory.wall_contacts.push_back(wall_id);
+            while trajectory.wall_contacts.len() > loop_bounces {
+                trajectory.wall_contacts.pop_front();
+            }
+        }
+    }
+
+    let min_angle = rules.ball.min_wall_angle.to_radians();
+    for (mut trajectory, mut velocity) in ball_query.iter_mut() {
+        if trajectory.is_looping(rules.ball.loop_bounces) {
+            velocity.linvel = Vec2::from_angle(min_angle).rotate(velocity.linvel);
+            trajectory.wall_contacts.clear();
+        }
+    }
+
+    // Only bouncing off a barrier may leave a ball sliding along it, shots
+    // parallel to a barrier elsewhere in the arena are fine
+    for hit in barrier_hits {
+        let side = match barrier_query.get(hit.barrier) {
+            Ok(barrier) => barrier.side,
+            Err(_) => continue,
+        };
+        if let Ok((_, mut velocity)) = ball_query.get_mut(hit.ball) {
+            velocity.linvel = guard_wall_angle(velocity.linvel, side, min_angle);
+        }
+    }
+}
+
+fn ball_speed_control_system(
+    mut query: Query<(&BallState, &BallKind, &mut Velocity), FreeBallFilter>,
+    rules: Res<GameRules>,
+) {
+    for (ball_state, kind, mut velocity) in query.iter_mut() {
         // ensure constant speed
-        ball_update_speed(velocity.linvel, &ball_state, &mut velocity);
+        ball_update_speed(
+            velocity.linvel,
+            ball_state,
+            kind,
+            &rules.ball,
+            &mut velocity,
+        );
     }
 }
 
+/// Turn the Rapier collisions involving a ball into gameplay events
+#[allow(clippy::too_many_arguments)]
 fn ball_collision_system(
-    mut events: EventReader<CollisionEvent>,
-    mut balls: Query<(&mut Velocity), With<Ball>>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut player_hits: EventWriter<BallHitPlayer>,
+    mut corner_hits: EventWriter<BallHitCorner>,
+    mut barrier_hits: EventWriter<BallHitBarrier>,
+    mut ball_hits: EventWriter<BallHitBall>,
+    mut power_up_hits: EventWriter<BallHitPowerUp>,
+    ball_query: Query<(), With<Ball>>,
+    player_query: Query<(), With<Player>>,
+    corner_query: Query<(), With<Corner>>,
+    barrier_query: Query<(), With<Barrier>>,
+    power_up_query: Query<(), With<PowerUp>>,
 ) {
-    for event in events.iter() {
-        match event {
-            CollisionEvent::Started(a, b, _) => {}
+    for event in collision_events.iter() {
+        let (a, b) = match event {
+            CollisionEvent::Started(a, b, _) => (*a, *b),
+            CollisionEvent::Stopped(..) => continue,
+        };
 
-            CollisionEvent::Stopped(a, b, _) => {
-                if let Ok(mut velocity) = balls.get_mut(*a) {
-                    let v = velocity.linvel.normalize() * BALL_SPEED;
+        if ball_query.contains(a) && ball_query.contains(b) {
+            ball_hits.send(BallHitBall { ball: a, other: b });
+            continue;
+        }
 
-                    *velocity = Velocity::linear(v);
-                }
+        for (ball, other) in [(a, b), (b, a)] {
+            if !ball_query.contains(ball) {
+                continue;
+            }
+
+            if player_query.contains(other) {
+                player_hits.send(BallHitPlayer {
+                    ball,
+                    player: other,
+                });
+            } else if corner_query.contains(other) {
+                corner_hits.send(BallHitCorner {
+                    ball,
+                    corner: other,
+                });
+            } else if barrier_query.contains(other) {
+                barrier_hits.send(BallHitBarrier {
+                    ball,
+                    barrier: other,
+                });
+            } else if power_up_query.contains(other) {
+                power_up_hits.send(BallHitPowerUp {
+                    ball,
+                    power_up: other,
+                });
+            }
+        }
+    }
+}
+
+/// Balls hitting a corner or a barrier, along with the wall they hit
+fn wall_hits<'a>(
+    corner_hits: &'a mut EventReader<BallHitCorner>,
+    barrier_hits: &'a mut EventReader<BallHitBarrier>,
+) -> impl Iterator<Item = (Entity, Entity)> + 'a {
+    corner_hits
+        .iter()
+        .map(|hit| (hit.ball, hit.corner))
+        .chain(barrier_hits.iter().map(|hit| (hit.ball, hit.barrier)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                if let Ok(mut velocity) = balls.get_mut(*b) {
-                    let v = velocity.linvel.normalize() * BALL_SPEED;
+    const MIN_ANGLE: f32 = 10.0 * PI / 180.0;
 
-                    *velocity = Velocity::linear(v);
+    /// Angle a velocity makes with the barrier of `side`, in radians
+    fn wall_angle(velocity: Vec2, side: Side) -> f32 {
+        let along = velocity.dot(side.tangent());
+        let across = velocity.dot(side.normal());
+        across.abs().atan2(along.abs())
+    }
+
+    fn assert_guarded(velocity: Vec2, side: Side) -> Vec2 {
+        let guarded = guard_wall_angle(velocity, side, MIN_ANGLE);
+        assert!((wall_angle(guarded, side) - MIN_ANGLE).abs() < 1e-4);
+        assert!((guarded.length() - velocity.length()).abs() < 1e-3);
+        // Still going the same way along the wall
+        assert!(guarded.dot(side.tangent()) * velocity.dot(side.tangent()) > 0.0);
+        guarded
+    }
+
+    #[test]
+    fn exactly_parallel_leaves_the_wall() {
+        for side in Side::ALL {
+            let velocity = side.tangent() * 300.0;
+            let guarded = assert_guarded(velocity, side);
+            // Away from the barrier, into the arena
+            assert!(guarded.dot(side.normal()) < 0.0);
+        }
+    }
+
+    #[test]
+    fn nearly_parallel_keeps_its_side() {
+        for side in Side::ALL {
+            for across in [1.0, -1.0] {
+                for along in [300.0, -300.0] {
+                    let velocity = side.tangent() * along + side.normal() * across;
+                    let guarded = assert_guarded(velocity, side);
+                    assert!(guarded.dot(side.normal()) * across > 0.0);
                 }
             }
         }
     }
+
+    #[test]
+    fn zero_velocity_is_unchanged() {
+        for side in Side::ALL {
+            assert_eq!(guard_wall_angle(Vec2::ZERO, side, MIN_ANGLE), Vec2::ZERO);
+        }
+    }
+
+    #[test]
+    fn steep_shot_is_unchanged() {
+        for side in Side::ALL {
+            let velocity = side.tangent() * 100.0 - side.normal() * 250.0;
+            assert_eq!(guard_wall_angle(velocity, side, MIN_ANGLE), velocity);
+        }
+    }
+
+    fn trajectory(walls: &[u32]) -> BallTrajectory {
+        BallTrajectory {
+            wall_contacts: walls.iter().map(|id| Entity::from_raw(*id)).collect(),
+        }
+    }
+
+    #[test]
+    fn two_walls_alternating_is_a_loop() {
+        assert!(trajectory(&[1, 2, 1, 2, 1, 2]).is_looping(6));
+    }
+
+    #[test]
+    fn single_wall_repeated_is_a_loop() {
+        assert!(trajectory(&[1; 6]).is_looping(6));
+    }
+
+    #[test]
+    fn three_walls_are_not_a_loop() {
+        assert!(!trajectory(&[1, 2, 3, 1, 2, 3]).is_looping(6));
+        assert!(!trajectory(&[1, 2, 1, 2, 1, 3]).is_looping(6));
+    }
+
+    #[test]
+    fn too_few_bounces_are_not_a_loop() {
+        assert!(!trajectory(&[1, 2, 1, 2]).is_looping(6));
+        assert!(!trajectory(&[1, 2]).is_looping(0));
+    }
+
+    fn deflection_rules(english: f32) -> DeflectionRules {
+        DeflectionRules {
+            model: DeflectionModel::Classic,
+            max_angle: 60.0,
+            english,
+        }
+    }
+
+    /// Angle of a deflected velocity from straight into the arena, in degrees,
+    /// positive towards the tangent of `side`
+    fn deflection_angle(velocity: Vec2, side: Side) -> f32 {
+        velocity
+            .dot(side.tangent())
+            .atan2(-velocity.dot(side.normal()))
+            .to_degrees()
+    }
+
+    #[test]
+    fn centre_hit_goes_straight_back() {
+        for side in Side::ALL {
+            let velocity = deflect(side, 0.0, 0.0, 300.0, &deflection_rules(0.0));
+            assert!((velocity - -side.normal() * 300.0).length() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn edge_hit_goes_out_at_max_angle() {
+        let rules = deflection_rules(0.0);
+        for side in Side::ALL {
+            for offset in [1.0, -1.0] {
+                let velocity = deflect(side, offset, 0.0, 300.0, &rules);
+                assert!((deflection_angle(velocity, side) - offset * rules.max_angle).abs() < 1e-3);
+            }
+            // Further than the edge is still the edge
+            let velocity = deflect(side, 2.0, 0.0, 300.0, &rules);
+            assert!((deflection_angle(velocity, side) - rules.max_angle).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn english_adds_along_the_tangent() {
+        for side in Side::ALL {
+            let still = deflect(side, 0.3, 0.0, 300.0, &deflection_rules(0.5));
+            let moving = deflect(side, 0.3, 200.0, 300.0, &deflection_rules(0.5));
+            assert!((moving - still - side.tangent() * 100.0).length() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn deflection_keeps_the_speed() {
+        for side in Side::ALL {
+            for offset in [-1.0, -0.4, 0.0, 0.7, 1.0] {
+                let velocity = deflect(side, offset, 0.0, 300.0, &deflection_rules(0.0));
+                assert!((velocity.length() - 300.0).abs() < 1e-3);
+            }
+        }
+    }
 }
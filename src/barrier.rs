@@ -1,6 +1,14 @@
 use std::f32::consts::PI;
 
-use crate::{util::Rectangle, ArenaInfo};
+use crate::{
+    arena::ArenaConfig,
+    corner::Corner,
+    game::{GameState, GameplayStep, RoundEntity, RoundSetup},
+    player::PlayerEliminated,
+    tick::TickApp,
+    util::{Rectangle, Side},
+    ArenaInfo, GoalSides,
+};
 use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
@@ -9,55 +17,99 @@ pub struct BarrierPlugin;
 
 impl Plugin for BarrierPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system_to_stage(StartupStage::PostStartup, barrier_spawn_system);
+        app.add_tick_system_set(
+            SystemSet::on_enter(GameState::Countdown).with_system(
+                barrier_spawn_system
+                    .label(RoundSetup::Barriers)
+                    .after(RoundSetup::Corners),
+            ),
+        )
+        .add_tick_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(
+                barrier_elimination_system
+                    .label(GameplayStep::Arena)
+                    .after(GameplayStep::Elimination),
+            ),
+        );
     }
 }
 
-const BARRIER_THICKNESS: f32 = 10.0;
-
 #[derive(Component)]
-struct Barrier;
+pub struct Barrier {
+    pub side: Side,
+}
+
+/// Corners and barriers, the fixed walls balls bounce off
+pub type WallFilter = Or<(With<Corner>, With<Barrier>)>;
+
+fn barrier_spawn_system(
+    mut commands: Commands,
+    arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+    goal_sides: Res<GoalSides>,
+) {
+    // Every side without a goal is closed by a barrier
+    for side in Side::ALL
+        .into_iter()
+        .filter(|side| !goal_sides.0.contains(side))
+    {
+        spawn_barrier(&mut commands, &arena_info, &arena_config, side);
+    }
+}
 
-fn barrier_spawn_system(mut commands: Commands, arena_info: Res<ArenaInfo>) {
+/// Close the side of an eliminated player
+fn barrier_elimination_system(
+    mut commands: Commands,
+    arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+    mut eliminated_events: EventReader<PlayerEliminated>,
+) {
+    for eliminated in eliminated_events.iter() {
+        spawn_barrier(&mut commands, &arena_info, &arena_config, eliminated.side);
+    }
+}
+
+/// Spawn a barrier closing `side`, removed along with the round
+pub fn spawn_barrier(
+    commands: &mut Commands,
+    arena_info: &ArenaInfo,
+    arena_config: &ArenaConfig,
+    side: Side,
+) -> Entity {
+    let length = arena_info.0.side_length(side);
+    let thickness = arena_config.barrier_thickness;
     let shape = shapes::Rectangle {
         extents: Vec2 {
-            x: arena_info.0.w,
-            y: BARRIER_THICKNESS,
+            x: length,
+            y: thickness,
         },
         ..Default::default()
     };
 
-    for (pos, angle) in [
-        (arena_info.0.left_middle(), -90.0),
-        (arena_info.0.top_middle(), 180.0),
-        (arena_info.0.right_middle(), 90.0),
-    ] {
-        commands
-            .spawn_bundle(GeometryBuilder::build_as(
-                &shape,
-                DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(
-                    Color::DARK_GRAY,
-                )),
-                Transform {
-                    translation: pos,
-                    rotation: Quat::from_rotation_z(angle / 180.0 * PI),
-                    ..Default::default()
-                },
-            ))
-            .insert(Barrier)
-            .insert(RigidBody::Fixed)
-            .insert(Restitution {
-                coefficient: 1.0,
-                combine_rule: CoefficientCombineRule::Min,
-            })
-            .insert(Friction {
-                coefficient: 0.0,
-                combine_rule: CoefficientCombineRule::Min,
-            })
-            .insert(Velocity::zero())
-            .insert(Collider::cuboid(
-                arena_info.0.w / 2.0,
-                BARRIER_THICKNESS / 2.0,
-            ));
-    }
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &shape,
+            DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(
+                Color::DARK_GRAY,
+            )),
+            Transform {
+                translation: arena_info.0.side_middle(side),
+                rotation: Quat::from_rotation_z(side.angle() / 180.0 * PI),
+                ..Default::default()
+            },
+        ))
+        .insert(Barrier { side })
+        .insert(RoundEntity)
+        .insert(RigidBody::Fixed)
+        .insert(Restitution {
+            coefficient: arena_config.restitution,
+            combine_rule: CoefficientCombineRule::Min,
+        })
+        .insert(Friction {
+            coefficient: arena_config.friction,
+            combine_rule: CoefficientCombineRule::Min,
+        })
+        .insert(Velocity::zero())
+        .insert(Collider::cuboid(length / 2.0, thickness / 2.0))
+        .id()
 }
@@ -0,0 +1,129 @@
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use crate::{
+    player::{KeyboardLayout, PlayerAction},
+    util::Side,
+};
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const CONFIG_FOLDER: &str = "rust-crashball-2d";
+const CONTROLS_FILE: &str = "controls.ron";
+
+/// Inputs bound to each action of a player
+type Bindings = Vec<(PlayerAction, Vec<UserInput>)>;
+
+/// Key and gamepad bindings of each side, kept in the user's config folder
+#[derive(Serialize, Deserialize, Default)]
+pub struct Controls {
+    /// Bindings of the sides moved away from `PlayerAction::default_key_map`,
+    /// for each keyboard layout the side was played with
+    players: BTreeMap<(Side, KeyboardLayout), Bindings>,
+}
+
+impl Controls {
+    /// Read the saved bindings, none being saved yet on the first run
+    pub fn load() -> Result<Self, String> {
+        let path = match controls_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+
+        let bytes =
+            fs::read(&path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        ron::de::from_bytes(&bytes)
+            .map_err(|err| format!("invalid file {}: {}", path.display(), err))
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = controls_path().ok_or("no config folder for the controls")?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)
+                .map_err(|err| format!("failed to create {}: {}", folder.display(), err))?;
+        }
+
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|err| format!("failed to save the controls: {}", err))?;
+        fs::write(&path, text).map_err(|err| format!("failed to write {}: {}", path.display(), err))
+    }
+
+    /// Bindings of the player on `side` with the keys of `layout`, for every
+    /// device
+    pub fn input_map(&self, side: Side, layout: KeyboardLayout) -> InputMap<PlayerAction> {
+        let bindings = match self.players.get(&(side, layout)) {
+            Some(bindings) => bindings,
+            None => return PlayerAction::default_key_map(layout),
+        };
+
+        let mut input_map = InputMap::default();
+        for (action, inputs) in bindings {
+            for input in inputs {
+                input_map.insert(input.clone(), *action);
+            }
+        }
+        input_map
+    }
+
+    /// Keep the bindings of `input_map`, but not its gamepad, which is picked
+    /// for each match
+    pub fn set_input_map(
+        &mut self,
+        side: Side,
+        layout: KeyboardLayout,
+        input_map: &InputMap<PlayerAction>,
+    ) {
+        let bindings = PlayerAction::variants()
+            .map(|action| (action, input_map.get(action).iter().cloned().collect()))
+            .collect();
+        self.players.insert((side, layout), bindings);
+    }
+
+    /// Go back to the default bindings of `layout` on `side`
+    pub fn reset(&mut self, side: Side, layout: KeyboardLayout) {
+        self.players.remove(&(side, layout));
+    }
+}
+
+/// Where the controls are saved, following each platform's convention
+fn controls_path() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let config_folder = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".config")))
+    }?;
+
+    Some(config_folder.join(CONFIG_FOLDER).join(CONTROLS_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::input::keyboard::KeyCode;
+
+    #[test]
+    fn saved_bindings_only_apply_to_their_layout() {
+        let mut input_map = PlayerAction::default_key_map(KeyboardLayout::Wasd);
+        input_map.insert(KeyCode::Q, PlayerAction::Energy);
+        let mut controls = Controls::default();
+        controls.set_input_map(Side::Left, KeyboardLayout::Wasd, &input_map);
+
+        let text = ron::to_string(&controls).unwrap();
+        let controls: Controls = ron::from_str(&text).unwrap();
+
+        let wasd = controls.input_map(Side::Left, KeyboardLayout::Wasd);
+        assert!(wasd
+            .get(PlayerAction::Energy)
+            .iter()
+            .any(|input| *input == KeyCode::Q.into()));
+        let arrows = controls.input_map(Side::Left, KeyboardLayout::Arrows);
+        assert_eq!(
+            arrows,
+            PlayerAction::default_key_map(KeyboardLayout::Arrows)
+        );
+    }
+}
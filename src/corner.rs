@@ -1,4 +1,12 @@
-use crate::{util::Rectangle, ArenaInfo};
+use std::{f32::consts::PI, time::Duration};
+
+use crate::{
+    arena::ArenaConfig,
+    game::{GameState, GameplayStep, RoundEntity, RoundSetup},
+    tick::TickApp,
+    util::Rectangle,
+    ArenaInfo, TIME_STEP,
+};
 use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
@@ -7,49 +15,147 @@ pub struct CornerPlugin;
 
 impl Plugin for CornerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system_to_stage(StartupStage::PostStartup, corner_spawn_system);
+        app.add_tick_system_set(
+            SystemSet::on_enter(GameState::Countdown).with_system(
+                corner_spawn_system
+                    .label(RoundSetup::Corners)
+                    .after(RoundSetup::Cleanup),
+            ),
+        )
+        .add_tick_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(
+                corner_animation_system
+                    .label(GameplayStep::Arena)
+                    .after(GameplayStep::Elimination),
+            ),
+        );
     }
 }
 
-pub const CORNER_RADIUS: f32 = 70.0;
+/// Corner of the arena, numbered from 0 to 3: top left, top right, bottom
+/// left and bottom right
+#[derive(Component)]
+pub struct Corner {
+    pub index: usize,
+}
+
+const CORNER_COLOR: Color = Color::DARK_GRAY;
+/// Seconds between two flashes of a corner about to eject a ball
+const WARNING_FLASH: f32 = 0.15;
+/// Seconds for a corner to get back to its colour after ejecting a ball
+const EJECT_DURATION: f32 = 0.3;
 
+/// Animation of a corner announcing a ball, then ejecting it
 #[derive(Component)]
-struct Corner;
+pub enum CornerAnimation {
+    /// Flashing until the ball comes out
+    Warning { flash: Timer, lit: bool },
+    /// Glowing and fading back as the ball comes out
+    Eject(Timer),
+}
+
+impl CornerAnimation {
+    pub fn warning() -> Self {
+        CornerAnimation::Warning {
+            flash: Timer::from_seconds(WARNING_FLASH, true),
+            lit: true,
+        }
+    }
 
-fn corner_spawn_system(mut commands: Commands, arena_info: Res<ArenaInfo>) {
+    pub fn eject() -> Self {
+        CornerAnimation::Eject(Timer::from_seconds(EJECT_DURATION, false))
+    }
+}
+
+/// Collision group of the corners, so that ghost balls can go through them
+pub const CORNER_GROUP: u32 = 1 << 1;
+
+fn corner_spawn_system(
+    mut commands: Commands,
+    arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+) {
     let shape = shapes::Circle {
-        radius: CORNER_RADIUS,
+        radius: arena_config.corner_radius,
         ..Default::default()
     };
 
-    for pos in [
+    for (index, pos) in [
         arena_info.0.top_left(),
         arena_info.0.top_right(),
         arena_info.0.bottom_left(),
         arena_info.0.bottom_right(),
-    ] {
+    ]
+    .into_iter()
+    .enumerate()
+    {
         commands
             .spawn_bundle(GeometryBuilder::build_as(
                 &shape,
-                DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(
-                    Color::DARK_GRAY,
-                )),
+                DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(CORNER_COLOR)),
                 Transform {
                     translation: pos,
                     ..Default::default()
                 },
             ))
-            .insert(Corner)
+            .insert(Corner { index })
+            .insert(RoundEntity)
             .insert(RigidBody::Fixed)
             .insert(Restitution {
-                coefficient: 1.0,
+                coefficient: arena_config.restitution,
                 combine_rule: CoefficientCombineRule::Min,
             })
             .insert(Friction {
-                coefficient: 0.0,
+                coefficient: arena_config.friction,
                 combine_rule: CoefficientCombineRule::Min,
             })
             .insert(Velocity::zero())
-            .insert(Collider::ball(CORNER_RADIUS));
+            .insert(Collider::ball(arena_config.corner_radius))
+            .insert(CollisionGroups {
+                memberships: CORNER_GROUP,
+                filters: u32::MAX,
+            });
+    }
+}
+
+fn corner_animation_system(
+    mut commands: Commands,
+    mut corner_query: Query<(Entity, &mut CornerAnimation, &mut DrawMode), With<Corner>>,
+) {
+    let delta = Duration::from_secs_f32(TIME_STEP);
+    for (corner, mut animation, mut draw_mode) in corner_query.iter_mut() {
+        let color = match animation.as_mut() {
+            CornerAnimation::Warning { flash, lit } => {
+                if flash.tick(delta).just_finished() {
+                    *lit = !*lit;
+                }
+                if *lit {
+                    Color::WHITE
+                } else {
+                    CORNER_COLOR
+                }
+            }
+            CornerAnimation::Eject(timer) => {
+                // Back to its colour, nothing left to animate unless the next
+                // ball was announced there during this tick
+                if timer.tick(delta).finished() {
+                    commands.add(move |world: &mut World| {
+                        if let Some(mut corner) = world.get_entity_mut(corner) {
+                            if matches!(corner.get(), Some(CornerAnimation::Eject(_))) {
+                                corner.remove::<CornerAnimation>();
+                            }
+                        }
+                    });
+                }
+                let glow = (timer.percent_left() * PI / 2.0).sin();
+                Color::rgb(
+                    CORNER_COLOR.r() + (1.0 - CORNER_COLOR.r()) * glow,
+                    CORNER_COLOR.g() + (1.0 - CORNER_COLOR.g()) * glow,
+                    CORNER_COLOR.b() + (1.0 - CORNER_COLOR.b()) * glow,
+                )
+            }
+        };
+
+        *draw_mode = DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(color));
     }
 }
@@ -0,0 +1,341 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    arena::ArenaConfig,
+    player::{Controller, InputDevice, PlayerAction, PlayerSetup, RoundOver},
+    rules::GameRules,
+    tick::TickApp,
+    util::Side,
+    GoalSides, TIME_STEP,
+};
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        let seed = app
+            .world
+            .get_resource::<GameSeed>()
+            .map_or_else(rand::random, |seed| seed.0);
+
+        app.add_tick_state(GameState::Menu)
+            .insert_resource(GameSeed(seed))
+            .insert_resource(GameRng(StdRng::seed_from_u64(seed)))
+            .init_resource::<GameRules>()
+            .init_resource::<GoalSides>()
+            .init_resource::<MatchScore>()
+            .insert_resource(CountdownTimer(Timer::from_seconds(
+                COUNTDOWN_SECONDS,
+                false,
+            )))
+            .insert_resource(RoundOverTimer(Timer::from_seconds(
+                ROUND_OVER_SECONDS,
+                false,
+            )))
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Menu).with_system(round_cleanup_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_exit(GameState::Menu).with_system(match_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Countdown)
+                    .with_system(round_cleanup_system.label(RoundSetup::Cleanup))
+                    .with_system(countdown_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Countdown).with_system(countdown_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Playing).with_system(physics_resume_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_resume(GameState::Playing).with_system(physics_resume_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        round_over_system
+                            .label(GameplayStep::Round)
+                            .after(GameplayStep::Arena),
+                    )
+                    .with_system(pause_system.after(round_over_system)),
+            )
+            .add_tick_system_set(
+                SystemSet::on_pause(GameState::Playing).with_system(physics_suspend_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_exit(GameState::Playing).with_system(physics_suspend_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::RoundOver).with_system(round_over_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::RoundOver).with_system(round_over_timer_system),
+            )
+            // Waiting for a key press, which may not last until the next tick. The
+            // state change waits for the next tick too, so a second press meanwhile
+            // is dropped
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(in_state(GameState::MatchOver))
+                    .with_system(match_over_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_exit(GameState::MatchOver).with_system(match_reset_system),
+            );
+    }
+}
+
+const COUNTDOWN_SECONDS: f32 = 3.0;
+const ROUND_OVER_SECONDS: f32 = 3.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GameState {
+    Menu,
+    Countdown,
+    Playing,
+    Paused,
+    RoundOver,
+    MatchOver,
+}
+
+/// Run criteria of the systems running on every frame in `state`, rather than
+/// on every tick like `SystemSet::on_update`
+pub fn in_state(state: GameState) -> impl Fn(Res<State<GameState>>) -> ShouldRun {
+    move |current: Res<State<GameState>>| {
+        if *current.current() == state {
+            ShouldRun::Yes
+        } else {
+            ShouldRun::No
+        }
+    }
+}
+
+/// Every entity tagged with this component is despawned between rounds
+#[derive(Component)]
+pub struct RoundEntity;
+
+/// Steps spawning a new round, each one running after the previous one so that
+/// entities are always spawned in the same order
+#[derive(SystemLabel, Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub enum RoundSetup {
+    Cleanup,
+    Corners,
+    Barriers,
+    GoalZones,
+    Players,
+}
+
+/// Steps of a gameplay tick, each one running after the previous one so that a
+/// match only depends on its seed and on the players input. Bevy would
+/// otherwise pick a different order for unrelated systems on every run.
+#[derive(SystemLabel, Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub enum GameplayStep {
+    /// CPU players press their actions
+    Ai,
+    /// Players move and fire energy
+    Player,
+    /// The round speeds up
+    Pacing,
+    /// Balls are spawned, sped up and removed
+    Ball,
+    /// Power-ups are spawned, picked and applied
+    PowerUp,
+    /// Goals are detected and counted
+    Score,
+    /// Players lose lives and get eliminated
+    Elimination,
+    /// The arena closes the side of eliminated players
+    Arena,
+    /// The round ends with the last player standing
+    Round,
+}
+
+/// Players taking part in the match
+pub struct MatchSetup {
+    pub players: Vec<PlayerSetup>,
+}
+
+impl MatchSetup {
+    /// Device of the human player on `side`, if any
+    pub fn device(&self, side: Side) -> Option<InputDevice> {
+        self.players
+            .iter()
+            .find(|setup| setup.side == side)
+            .and_then(|setup| match setup.controller {
+                Controller::Human(device) => Some(device),
+                _ => None,
+            })
+    }
+}
+
+/// Rounds won by each side in the current match
+#[derive(Default)]
+pub struct MatchScore {
+    pub round_wins: HashMap<Side, u32>,
+    pub last_winner: Option<Side>,
+}
+
+impl MatchScore {
+    pub fn rounds_won(&self, side: Side) -> u32 {
+        self.round_wins.get(&side).copied().unwrap_or(0)
+    }
+
+    pub fn match_winner(&self, rounds_to_win: u32) -> Option<Side> {
+        self.round_wins
+            .iter()
+            .find(|(_, wins)| **wins >= rounds_to_win)
+            .map(|(side, _)| *side)
+    }
+}
+
+/// Seed of every random decision taken during a match
+pub struct GameSeed(pub u64);
+
+/// Source of randomness of the gameplay systems, seeded from `GameSeed` at the
+/// start of every match
+pub struct GameRng(pub StdRng);
+
+pub struct CountdownTimer(pub Timer);
+
+struct RoundOverTimer(Timer);
+
+fn start_pressed(
+    keyboard: &Input<KeyCode>,
+    gamepads: &Gamepads,
+    gamepad_buttons: &Input<GamepadButton>,
+) -> bool {
+    keyboard.just_pressed(KeyCode::Return)
+        || gamepads.iter().any(|gamepad| {
+            gamepad_buttons.just_pressed(GamepadButton::new(*gamepad, GamepadButtonType::Start))
+        })
+}
+
+fn match_reset_system(
+    mut match_score: ResMut<MatchScore>,
+    mut rng: ResMut<GameRng>,
+    seed: Res<GameSeed>,
+) {
+    *match_score = MatchScore::default();
+    rng.0 = StdRng::seed_from_u64(seed.0);
+    info!("Match seed: {}", seed.0);
+}
+
+fn round_cleanup_system(
+    mut commands: Commands,
+    mut goal_sides: ResMut<GoalSides>,
+    match_setup: Res<MatchSetup>,
+    arena_config: Res<ArenaConfig>,
+    query: Query<Entity, With<RoundEntity>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // Players whose side is a wall in this arena sit the round out
+    goal_sides.0 = match_setup
+        .players
+        .iter()
+        .map(|player| player.side)
+        .filter(|side| arena_config.goals.contains(side))
+        .collect();
+}
+
+fn countdown_reset_system(mut countdown: ResMut<CountdownTimer>) {
+    countdown.0.reset();
+}
+
+fn countdown_system(mut state: ResMut<State<GameState>>, mut countdown: ResMut<CountdownTimer>) {
+    if countdown
+        .0
+        .tick(Duration::from_secs_f32(TIME_STEP))
+        .just_finished()
+    {
+        state.set(GameState::Playing).unwrap();
+    }
+}
+
+fn physics_resume_system(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = true;
+}
+
+fn physics_suspend_system(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+fn pause_system(
+    mut state: ResMut<State<GameState>>,
+    mut action_query: Query<&mut ActionState<PlayerAction>>,
+) {
+    let mut pause_pressed = false;
+    for mut action_state in action_query.iter_mut() {
+        if action_state.just_pressed(PlayerAction::Pause) {
+            // Consumed so that the pause menu, running on the next frames until
+            // the actions are read again, doesn't take it as a resume
+            action_state.consume(PlayerAction::Pause);
+            pause_pressed = true;
+        }
+    }
+
+    // Too late once the round is over
+    if pause_pressed {
+        let _ = state.push(GameState::Paused);
+    }
+}
+
+fn round_over_system(
+    mut state: ResMut<State<GameState>>,
+    mut match_score: ResMut<MatchScore>,
+    mut round_events: EventReader<RoundOver>,
+) {
+    if let Some(round) = round_events.iter().last() {
+        if let Some(winner) = round.winner {
+            *match_score.round_wins.entry(winner).or_insert(0) += 1;
+        }
+        match_score.last_winner = round.winner;
+
+        state.set(GameState::RoundOver).unwrap();
+    }
+}
+
+fn round_over_reset_system(mut round_over_timer: ResMut<RoundOverTimer>) {
+    round_over_timer.0.reset();
+}
+
+fn round_over_timer_system(
+    mut state: ResMut<State<GameState>>,
+    mut round_over_timer: ResMut<RoundOverTimer>,
+    match_score: Res<MatchScore>,
+    rules: Res<GameRules>,
+) {
+    if round_over_timer
+        .0
+        .tick(Duration::from_secs_f32(TIME_STEP))
+        .just_finished()
+    {
+        if match_score.match_winner(rules.rounds_to_win).is_some() {
+            state.set(GameState::MatchOver).unwrap();
+        } else {
+            state.set(GameState::Countdown).unwrap();
+        }
+    }
+}
+
+fn match_over_system(
+    mut state: ResMut<State<GameState>>,
+    keyboard: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+) {
+    if start_pressed(&keyboard, &gamepads, &gamepad_buttons) {
+        // Restart a new match with the same setup
+        let _ = state.set(GameState::Countdown);
+    } else if keyboard.just_pressed(KeyCode::Escape) {
+        let _ = state.set(GameState::Menu);
+    }
+}
@@ -0,0 +1,542 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crate::{
+    ai::Difficulty,
+    arena::{ArenaConfig, DEFAULT_ARENA_FILE},
+    ball::Ball,
+    game::{GameSeed, GameState, MatchScore, MatchSetup},
+    player::{Controller, PlayerSetup},
+    replay::{self, Replay, ReplayRecorder},
+    rules::{GameRules, DEFAULT_RULES_PRESET},
+    setup_physic,
+    tick::TickApp,
+    util::Side,
+    GameplayPlugins, TIME_STEP,
+};
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    hierarchy::HierarchyPlugin,
+    input::InputPlugin,
+    prelude::*,
+    time::TimePlugin,
+    transform::TransformPlugin,
+};
+use bevy_rapier2d::prelude::*;
+
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Time>()
+            .init_resource::<SimulationStats>()
+            .add_system_to_stage(CoreStage::First, simulation_time_system)
+            .add_system_to_stage(CoreStage::First, simulation_tick_system)
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Menu).with_system(match_start_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Countdown).with_system(round_start_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::RoundOver).with_system(round_report_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::MatchOver).with_system(match_report_system),
+            );
+    }
+}
+
+/// Settings of a headless run, read from the command line
+#[derive(Clone)]
+pub struct HeadlessConfig {
+    pub matches: u32,
+    /// Arena file, relative to the assets folder
+    pub arena: String,
+    /// Overrides the size given by the arena file
+    pub arena_size: Option<(f32, f32)>,
+    /// Preset of `assets/rules`, or rules file relative to the assets folder
+    pub rules: String,
+    pub difficulty: Difficulty,
+    pub seed: Option<u64>,
+    /// Run the first `ticks` of a match twice and compare the outcome instead
+    /// of playing matches
+    pub check_determinism: bool,
+    pub ticks: u32,
+    /// File the last match played is saved to
+    pub record: Option<PathBuf>,
+    /// Recorded match to play back instead of CPU matches
+    pub replay: Option<PathBuf>,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            matches: 1,
+            arena: DEFAULT_ARENA_FILE.to_string(),
+            arena_size: None,
+            rules: DEFAULT_RULES_PRESET.to_string(),
+            difficulty: Difficulty::Normal,
+            seed: None,
+            check_determinism: false,
+            ticks: 3600,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+impl HeadlessConfig {
+    pub const USAGE: &'static str = "usage: rust-crashball-2d --headless [--matches <count>] \
+         [--arena <file>] [--arena-size <width>x<height>] \
+         [--rules classic|chaos|practice|<file>] [--difficulty easy|normal|hard] [--seed <seed>] \
+         [--check-determinism [--ticks <count>]] [--record <file>] [--replay <file>]";
+
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = HeadlessConfig::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}\n{}", arg, Self::USAGE))
+            };
+
+            match arg.as_str() {
+                "--headless" => {}
+                "--check-determinism" => config.check_determinism = true,
+                "--seed" => {
+                    config.seed = Some(
+                        value()?
+                            .parse()
+                            .map_err(|_| format!("invalid seed\n{}", Self::USAGE))?,
+                    );
+                }
+                "--ticks" => {
+                    config.ticks = value()?
+                        .parse()
+                        .map_err(|_| format!("invalid tick count\n{}", Self::USAGE))?;
+                }
+                "--record" => config.record = Some(value()?.into()),
+                "--replay" => config.replay = Some(value()?.into()),
+                "--matches" => {
+                    config.matches = value()?
+                        .parse()
+                        .ok()
+                        .filter(|matches| *matches >= 1)
+                        .ok_or_else(|| format!("invalid match count\n{}", Self::USAGE))?;
+                }
+                "--arena" => config.arena = value()?.clone(),
+                "--rules" => config.rules = value()?.clone(),
+                "--arena-size" => {
+                    let size = value()?
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|(w, h): &(f32, f32)| {
+                            w.is_finite() && h.is_finite() && *w > 0.0 && *h > 0.0
+                        })
+                        .ok_or_else(|| format!("invalid arena size\n{}", Self::USAGE))?;
+                    config.arena_size = Some(size);
+                }
+                "--difficulty" => {
+                    config.difficulty = match value()?.as_str() {
+                        "easy" => Difficulty::Easy,
+                        "normal" => Difficulty::Normal,
+                        "hard" => Difficulty::Hard,
+                        _ => return Err(format!("invalid difficulty\n{}", Self::USAGE)),
+                    };
+                }
+                _ => return Err(format!("unknown argument {}\n{}", arg, Self::USAGE)),
+            }
+        }
+
+        // A replay holds a single match
+        if config.replay.is_some() {
+            config.matches = 1;
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Default)]
+struct SimulationStats {
+    tick: u64,
+    round_start_tick: u64,
+    matches_played: u32,
+    match_wins: HashMap<Side, u32>,
+}
+
+/// Run matches between CPU players without a window, as fast as possible
+pub fn run(config: HeadlessConfig) {
+    if config.check_determinism {
+        check_determinism(config);
+        return;
+    }
+
+    build_app(config).add_plugin(ScheduleRunnerPlugin).run();
+}
+
+fn build_app(config: HeadlessConfig) -> App {
+    let mut app = App::new();
+
+    if let Some(seed) = config.seed {
+        app.insert_resource(GameSeed(seed));
+    }
+
+    let mut arena_config = match ArenaConfig::load(&config.arena) {
+        Ok(arena_config) => arena_config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some((width, height)) = config.arena_size {
+        arena_config.width = width;
+        arena_config.height = height;
+        if let Err(err) = arena_config.validate() {
+            eprintln!("invalid arena size: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let rules = match GameRules::load(&config.rules) {
+        Ok(rules) => rules,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    app.insert_resource(arena_config)
+        .insert_resource(rules)
+        .insert_resource(MatchSetup {
+            players: Side::ALL
+                .into_iter()
+                .map(|side| PlayerSetup {
+                    side,
+                    controller: Controller::Cpu(config.difficulty),
+                })
+                .collect(),
+        });
+
+    if let Some(path) = &config.record {
+        app.insert_resource(ReplayRecorder::new(path.clone()));
+    }
+    if let Some(path) = &config.replay {
+        match Replay::load(path) {
+            Ok(replay) => replay::insert_replay(&mut app, replay),
+            Err(err) => {
+                eprintln!("failed to load replay {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    app.insert_resource(config)
+        .add_plugins_with(MinimalPlugins, |group| {
+            group
+                .disable::<TimePlugin>()
+                .disable::<ScheduleRunnerPlugin>()
+        })
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugins(GameplayPlugins)
+        .add_plugin(HeadlessPlugin)
+        .add_startup_system(setup_physic);
+
+    app
+}
+
+/// Position of every ball after the first `ticks` of a match
+fn simulate_ball_positions(config: HeadlessConfig, seed: u64, ticks: u32) -> Vec<[u32; 2]> {
+    let mut app = build_app(HeadlessConfig {
+        seed: Some(seed),
+        ..config
+    });
+
+    for _ in 0..ticks {
+        app.update();
+    }
+
+    let mut query = app
+        .world
+        .query_filtered::<(Entity, &Transform), With<Ball>>();
+    let mut balls: Vec<(Entity, &Transform)> = query.iter(&app.world).collect();
+    balls.sort_by_key(|(entity, _)| *entity);
+
+    balls
+        .into_iter()
+        .map(|(_, transform)| {
+            [
+                transform.translation.x.to_bits(),
+                transform.translation.y.to_bits(),
+            ]
+        })
+        .collect()
+}
+
+/// Play the same match twice and make sure both runs end bit-identical
+fn check_determinism(config: HeadlessConfig) {
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let ticks = config.ticks;
+    let copy = || HeadlessConfig {
+        seed: Some(seed),
+        check_determinism: false,
+        record: None,
+        ..config.clone()
+    };
+
+    let first = simulate_ball_positions(copy(), seed, ticks);
+    let second = simulate_ball_positions(copy(), seed, ticks);
+
+    if first.is_empty() || first != second {
+        eprintln!(
+            "determinism check failed for seed {} after {} ticks ({} and {} balls)",
+            seed,
+            ticks,
+            first.len(),
+            second.len()
+        );
+        std::process::exit(1);
+    }
+
+    println!(
+        "determinism check passed for seed {}: {} balls identical after {} ticks",
+        seed,
+        first.len(),
+        ticks
+    );
+}
+
+/// Advance the clock by one physics step per frame, whatever the real elapsed time
+fn simulation_time_system(mut time: ResMut<Time>) {
+    let last_update = time.last_update().unwrap_or_else(|| time.startup());
+    time.update_with_instant(last_update + Duration::from_secs_f32(TIME_STEP));
+}
+
+fn simulation_tick_system(mut stats: ResMut<SimulationStats>) {
+    stats.tick += 1;
+}
+
+fn match_start_system(mut state: ResMut<State<GameState>>) {
+    state.set(GameState::Countdown).unwrap();
+}
+
+fn round_start_system(mut stats: ResMut<SimulationStats>) {
+    stats.round_start_tick = stats.tick;
+}
+
+fn round_report_system(stats: Res<SimulationStats>, match_score: Res<MatchScore>) {
+    let winner = match match_score.last_winner {
+        Some(side) => format!("{:?}", side),
+        None => "nobody".to_string(),
+    };
+    println!(
+        "match {} round won by {} after {} ticks",
+        stats.matches_played + 1,
+        winner,
+        stats.tick - stats.round_start_tick
+    );
+}
+
+fn match_report_system(
+    mut state: ResMut<State<GameState>>,
+    mut stats: ResMut<SimulationStats>,
+    mut exit: EventWriter<AppExit>,
+    mut seed: ResMut<GameSeed>,
+    config: Res<HeadlessConfig>,
+    match_score: Res<MatchScore>,
+    rules: Res<GameRules>,
+) {
+    stats.matches_played += 1;
+    if let Some(winner) = match_score.match_winner(rules.rounds_to_win) {
+        *stats.match_wins.entry(winner).or_insert(0) += 1;
+        println!(
+            "match {} (seed {}) won by {:?}",
+            stats.matches_played, seed.0, winner
+        );
+    }
+
+    if stats.matches_played < config.matches {
+        // Every match gets its own seed so they can be replayed one by one
+        seed.0 = seed.0.wrapping_add(1);
+        state.set(GameState::Countdown).unwrap();
+        return;
+    }
+
+    println!(
+        "{} matches played in {} ticks",
+        stats.matches_played, stats.tick
+    );
+    for side in Side::ALL {
+        println!(
+            "  {:?}: {} wins",
+            side,
+            stats.match_wins.get(&side).copied().unwrap_or(0)
+        );
+    }
+    exit.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        player::{EnergyMeter, InputDevice, KeyboardLayout, Player},
+        replay::ReplayPlayer,
+    };
+
+    /// Play the first `ticks` of the same match twice, expecting bit-identical
+    /// ball positions
+    fn assert_deterministic(config: HeadlessConfig, seed: u64, ticks: u32) {
+        let first = simulate_ball_positions(config.clone(), seed, ticks);
+        let second = simulate_ball_positions(config, seed, ticks);
+
+        assert!(!first.is_empty(), "no ball left after {} ticks", ticks);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn classic_match_is_deterministic() {
+        assert_deterministic(HeadlessConfig::default(), 42, 1200);
+    }
+
+    #[test]
+    fn chaos_match_is_deterministic() {
+        let config = HeadlessConfig {
+            rules: "chaos".to_string(),
+            ..Default::default()
+        };
+        assert_deterministic(config, 7, 1600);
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn match_count_must_be_positive() {
+        assert!(HeadlessConfig::from_args(&args(&["--headless", "--matches", "0"])).is_err());
+        assert!(HeadlessConfig::from_args(&args(&["--headless", "--matches", "-1"])).is_err());
+
+        let config = HeadlessConfig::from_args(&args(&["--headless", "--matches", "3"])).unwrap();
+        assert_eq!(config.matches, 3);
+    }
+
+    #[test]
+    fn arena_size_must_be_positive() {
+        for size in ["0x0", "-5x100", "NaNxinf", "100x"] {
+            let config = HeadlessConfig::from_args(&args(&["--headless", "--arena-size", size]));
+            assert!(config.is_err(), "{} accepted", size);
+        }
+
+        let config =
+            HeadlessConfig::from_args(&args(&["--headless", "--arena-size", "600x400"])).unwrap();
+        assert_eq!(config.arena_size, Some((600.0, 400.0)));
+    }
+
+    #[test]
+    fn recording_is_saved_when_leaving_mid_match() {
+        let path = std::env::temp_dir().join("crashball-exit-test.cbrp");
+        let _ = std::fs::remove_file(&path);
+
+        let mut app = build_app(HeadlessConfig {
+            seed: Some(3),
+            record: Some(path.clone()),
+            ..Default::default()
+        });
+        for _ in 0..300 {
+            app.update();
+        }
+        app.world.send_event(AppExit);
+        app.update();
+
+        let replay = Replay::load(&path).expect("no replay saved");
+        assert_eq!(replay.seed, 3);
+        assert!(!replay.ticks.is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Ball positions and energy of every player after `ticks`, the energy key
+    /// of the bottom player being held from `hold.0` to `hold.1`
+    fn run_with_held_energy(
+        config: HeadlessConfig,
+        hold: (u32, u32),
+        ticks: u32,
+    ) -> (Vec<[u32; 2]>, Vec<u32>) {
+        let mut app = build_app(config);
+        if app.world.get_resource::<ReplayPlayer>().is_none() {
+            app.world.resource_mut::<MatchSetup>().players[0].controller =
+                Controller::Human(InputDevice::Keyboard(KeyboardLayout::Arrows));
+        }
+
+        for tick in 0..ticks {
+            let mut keyboard = app.world.resource_mut::<Input<KeyCode>>();
+            if tick == hold.0 {
+                keyboard.press(KeyCode::Space);
+            } else if tick == hold.1 {
+                keyboard.release(KeyCode::Space);
+            }
+            app.update();
+        }
+        app.world.send_event(AppExit);
+        app.update();
+
+        let mut ball_query = app
+            .world
+            .query_filtered::<(Entity, &Transform), With<Ball>>();
+        let mut player_query = app.world.query::<(&Player, &EnergyMeter)>();
+
+        let mut balls: Vec<(Entity, &Transform)> = ball_query.iter(&app.world).collect();
+        balls.sort_by_key(|(entity, _)| *entity);
+        let mut meters: Vec<(&Player, &EnergyMeter)> = player_query.iter(&app.world).collect();
+        meters.sort_by_key(|(player, _)| player.side as u8);
+
+        (
+            balls
+                .into_iter()
+                .map(|(_, transform)| {
+                    [
+                        transform.translation.x.to_bits(),
+                        transform.translation.y.to_bits(),
+                    ]
+                })
+                .collect(),
+            meters
+                .into_iter()
+                .map(|(_, meter)| meter.value.to_bits())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn replay_keeps_an_action_held_into_the_round() {
+        let path = std::env::temp_dir().join("crashball-held-test.cbrp");
+        let _ = std::fs::remove_file(&path);
+
+        // Held from the middle of the countdown until well into the round
+        let hold = (100, 400);
+        let live = run_with_held_energy(
+            HeadlessConfig {
+                seed: Some(11),
+                record: Some(path.clone()),
+                ..Default::default()
+            },
+            hold,
+            420,
+        );
+        let replayed = run_with_held_energy(
+            HeadlessConfig {
+                replay: Some(path.clone()),
+                ..Default::default()
+            },
+            hold,
+            420,
+        );
+        let _ = std::fs::remove_file(&path);
+
+        assert!(!live.0.is_empty(), "no ball left after 420 ticks");
+        assert_eq!(live, replayed);
+    }
+}
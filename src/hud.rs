@@ -0,0 +1,294 @@
+use crate::{
+    ball::BallPopulation,
+    game::{GameState, MatchSetup},
+    pacing::Pacing,
+    player::{player_color, Controller, EnergyMeter, Lives, Player},
+    rules::GameRules,
+    score::Score,
+    util::Side,
+    ArenaInfo, GoalSides,
+};
+use bevy::prelude::*;
+
+/// Lives, goals and energy of every side around the arena, along with the
+/// round time and the ball count
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(hud_spawn_system)
+            .add_system(hud_layout_system)
+            .add_system(hud_side_system)
+            .add_system(hud_energy_system.after(hud_side_system))
+            .add_system(hud_round_system);
+    }
+}
+
+const HUD_FONT_SIZE: f32 = 15.0;
+const HUD_DIM_COLOR: Color = Color::GRAY;
+
+/// Strip of the window margin along a side of the arena
+#[derive(Component)]
+struct HudPanel {
+    side: Side,
+}
+
+#[derive(Component)]
+struct HudSideText {
+    side: Side,
+}
+
+/// Corner of the window margin showing the round time and the ball count
+#[derive(Component)]
+struct HudRoundPanel;
+
+#[derive(Component)]
+struct HudRoundText;
+
+// Sections of a side text
+const NAME_SECTION: usize = 0;
+const LIVES_SECTION: usize = 2;
+const GOALS_SECTION: usize = 4;
+const ENERGY_SECTION: usize = 6;
+
+fn panel_bundle() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        color: Color::NONE.into(),
+        ..Default::default()
+    }
+}
+
+fn hud_spawn_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = TextStyle {
+        font: asset_server.load("fonts/DejaVuSans.ttf"),
+        font_size: HUD_FONT_SIZE,
+        color: Color::WHITE,
+    };
+
+    for side in Side::ALL {
+        commands
+            .spawn_bundle(panel_bundle())
+            .insert(HudPanel { side })
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(
+                        TextBundle::from_sections(
+                            (0..=ENERGY_SECTION).map(|_| TextSection::new("", style.clone())),
+                        )
+                        .with_text_alignment(TextAlignment::CENTER),
+                    )
+                    .insert(HudSideText { side });
+            });
+    }
+
+    commands
+        .spawn_bundle(panel_bundle())
+        .insert(HudRoundPanel)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section("", style.clone())
+                        .with_text_alignment(TextAlignment::CENTER),
+                )
+                .insert(HudRoundText);
+        });
+}
+
+/// Lay the panels in the window margin around the arena
+fn hud_layout_system(
+    arena_info: Res<ArenaInfo>,
+    windows: Res<Windows>,
+    mut laid_out: Local<bool>,
+    mut side_query: Query<(&HudPanel, &mut Style), Without<HudRoundPanel>>,
+    mut round_query: Query<&mut Style, With<HudRoundPanel>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    if *laid_out && !arena_info.is_changed() && !windows.is_changed() {
+        return;
+    }
+    *laid_out = true;
+
+    // The camera is centred on the arena
+    let left = arena_info.0.left() + window.width() / 2.0;
+    let right = arena_info.0.right() + window.width() / 2.0;
+    let bottom = arena_info.0.bottom() + window.height() / 2.0;
+    let top = arena_info.0.top() + window.height() / 2.0;
+
+    for (panel, mut style) in side_query.iter_mut() {
+        let (x, y, width, height) = match panel.side {
+            Side::Bottom => (left, 0.0, right - left, bottom),
+            Side::Top => (left, top, right - left, window.height() - top),
+            Side::Left => (0.0, bottom, left, top - bottom),
+            Side::Right => (right, bottom, window.width() - right, top - bottom),
+        };
+        style.position = UiRect {
+            left: Val::Px(x),
+            bottom: Val::Px(y),
+            ..Default::default()
+        };
+        style.size = Size::new(Val::Px(width), Val::Px(height));
+    }
+
+    for mut style in round_query.iter_mut() {
+        style.position = UiRect {
+            left: Val::Px(0.0),
+            bottom: Val::Px(top),
+            ..Default::default()
+        };
+        style.size = Size::new(Val::Px(left), Val::Px(window.height() - top));
+    }
+}
+
+fn hud_side_system(
+    state: Res<State<GameState>>,
+    score: Res<Score>,
+    match_setup: Res<MatchSetup>,
+    goal_sides: Res<GoalSides>,
+    player_query: Query<(&Player, &Lives)>,
+    mut text_query: Query<(&HudSideText, &mut Text)>,
+) {
+    for (hud_text, mut text) in text_query.iter_mut() {
+        let side = hud_text.side;
+        let setup = match_setup.players.iter().find(|setup| setup.side == side);
+
+        let setup = match setup {
+            Some(setup) if *state.current() != GameState::Menu => setup,
+            _ => {
+                for section in text.sections.iter_mut() {
+                    section.value.clear();
+                }
+                continue;
+            }
+        };
+
+        // Narrow margins on the left and right, one stat per line
+        let separator = match side {
+            Side::Bottom | Side::Top => "   ",
+            Side::Left | Side::Right => "\n",
+        };
+        let name = match setup.controller {
+            Controller::Cpu(_) => format!("{:?}{}CPU", side, separator),
+            _ => format!("{:?}", side),
+        };
+
+        let player = player_query.iter().find(|(player, ..)| player.side == side);
+        // The energy of a player in the arena is written by `hud_energy_system`
+        let lives = match player {
+            Some((_, lives)) => format!("Lives {}", lives.0),
+            None if goal_sides.0.contains(&side) => String::new(),
+            None => "OUT".to_string(),
+        };
+        if player.is_none() && !text.sections[ENERGY_SECTION].value.is_empty() {
+            text.sections[ENERGY_SECTION].value.clear();
+        }
+
+        let values = [
+            (NAME_SECTION, name, player_color(side)),
+            (LIVES_SECTION, lives, Color::WHITE),
+            (
+                GOALS_SECTION,
+                format!("Goals {}", score.goals_for(side)),
+                Color::WHITE,
+            ),
+        ];
+        for (idx, value, color) in values {
+            let section = &mut text.sections[idx];
+            if section.value != value {
+                section.value = value;
+            }
+            if section.style.color != color {
+                section.style.color = color;
+            }
+        }
+        for idx in [LIVES_SECTION - 1, GOALS_SECTION - 1, ENERGY_SECTION - 1] {
+            if text.sections[idx].value != separator {
+                text.sections[idx].value = separator.to_string();
+            }
+        }
+    }
+}
+
+/// Seconds until a player can fire its next pulse, waiting for both the
+/// cooldown and the energy
+fn pulse_wait(meter: &EnergyMeter, rules: &GameRules) -> f32 {
+    let cooldown = meter.cooldown.duration().as_secs_f32() - meter.cooldown.elapsed_secs();
+    let missing = rules.energy.pulse_cost - meter.value;
+    let regen = if missing <= 0.0 {
+        0.0
+    } else if rules.energy.regen > 0.0 {
+        missing / rules.energy.regen
+    } else {
+        f32::INFINITY
+    };
+    cooldown.max(regen)
+}
+
+fn hud_energy_system(
+    state: Res<State<GameState>>,
+    rules: Res<GameRules>,
+    meter_query: Query<(&Player, &EnergyMeter), Changed<EnergyMeter>>,
+    mut text_query: Query<(&HudSideText, &mut Text)>,
+) {
+    if *state.current() == GameState::Menu {
+        return;
+    }
+
+    for (player, meter) in meter_query.iter() {
+        let wait = pulse_wait(meter, &rules);
+        let (value, color) = if wait <= 0.0 {
+            ("Pulse ready".to_string(), Color::WHITE)
+        } else if wait.is_finite() {
+            (format!("Pulse in {:.1}s", wait), HUD_DIM_COLOR)
+        } else {
+            ("No pulse".to_string(), HUD_DIM_COLOR)
+        };
+
+        for (hud_text, mut text) in text_query.iter_mut() {
+            if hud_text.side != player.side {
+                continue;
+            }
+            let section = &mut text.sections[ENERGY_SECTION];
+            if section.value != value {
+                section.value = value.clone();
+            }
+            if section.style.color != color {
+                section.style.color = color;
+            }
+        }
+    }
+}
+
+fn hud_round_system(
+    state: Res<State<GameState>>,
+    pacing: Res<Pacing>,
+    population: Res<BallPopulation>,
+    mut text_query: Query<&mut Text, With<HudRoundText>>,
+) {
+    let message = if *state.current() == GameState::Menu {
+        String::new()
+    } else {
+        let seconds = pacing.elapsed as u32;
+        format!(
+            "{}:{:02}\nBalls {}/{}",
+            seconds / 60,
+            seconds % 60,
+            population.current,
+            population.max
+        )
+    };
+
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != message {
+            text.sections[0].value = message.clone();
+        }
+    }
+}
@@ -1,19 +1,47 @@
 #![allow(unused)]
 
+mod ai;
+mod arena;
 mod ball;
 mod barrier;
+mod controls;
 mod corner;
+mod game;
+mod headless;
+mod hud;
+mod menu;
+mod overlay;
+mod pacing;
 mod player;
+mod powerup;
+mod replay;
+mod rules;
+mod score;
+mod tick;
 mod util;
 
+use ai::{AiPlugin, Difficulty};
+use arena::{ArenaConfig, ArenaFile, ArenaHotReloadPlugin, ArenaPlugin, DEFAULT_ARENA_FILE};
 use ball::BallPlugin;
 use barrier::BarrierPlugin;
-use bevy::prelude::*;
+use bevy::{app::PluginGroupBuilder, asset::AssetServerSettings, prelude::*};
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
+use controls::Controls;
 use corner::CornerPlugin;
-use player::PlayerPlugin;
-use util::{clamp, Rectangle};
+use game::{GamePlugin, GameSeed, MatchSetup};
+use headless::HeadlessConfig;
+use hud::HudPlugin;
+use menu::MenuPlugin;
+use overlay::OverlayPlugin;
+use pacing::PacingPlugin;
+use player::{Controller, InputDevice, KeyboardLayout, PlayerPlugin, PlayerSetup};
+use powerup::PowerUpPlugin;
+use replay::{Replay, ReplayPlugin, ReplayRecorder};
+use rules::{GameRules, RulesPreset, DEFAULT_RULES_PRESET};
+use score::ScorePlugin;
+use tick::{RealTimeTickPlugin, TickPlugin};
+use util::{clamp, Rectangle, Side};
 
 // region:  -- Resources
 pub struct WinSize {
@@ -23,36 +51,183 @@ pub struct WinSize {
 
 pub struct ArenaInfo(Rectangle);
 
+impl ArenaInfo {
+    /// Arena of the given size centered on the origin
+    pub fn centered(w: f32, h: f32) -> Self {
+        ArenaInfo(Rectangle {
+            x: -w / 2.0,
+            y: -h / 2.0,
+            w,
+            h,
+        })
+    }
+}
+
+/// Sides of the arena left open, each one guarded by a player
+#[derive(Default)]
+pub struct GoalSides(pub Vec<Side>);
+
 // endregion
 
 // region:  -- Game constants
 
 const TIME_STEP: f32 = 1.0 / 60.0;
+const ARENA_MARGIN: f32 = 50.0;
 
 // endregion
 
+/// Every plugin needed to run the game rules, with or without a window
+pub struct GameplayPlugins;
+
+impl PluginGroup for GameplayPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group
+            .add(TickPlugin)
+            .add(
+                RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                    .with_default_system_setup(false),
+            )
+            .add(ArenaPlugin)
+            .add(GamePlugin)
+            .add(PlayerPlugin)
+            .add(AiPlugin)
+            .add(CornerPlugin)
+            .add(BarrierPlugin)
+            .add(BallPlugin)
+            .add(PacingPlugin)
+            .add(PowerUpPlugin)
+            .add(ScorePlugin)
+            .add(ReplayPlugin);
+    }
+}
+
 fn main() {
-    App::new()
-        .insert_resource(Msaa { samples: 4 })
-        .insert_resource(ClearColor(Color::rgb(0.04, 0.04, 0.04)))
-        .insert_resource(WindowDescriptor {
-            title: "Crash Ball".to_string(),
-            width: 850.,
-            height: 850.,
-            ..Default::default()
-        })
-        .add_plugins(DefaultPlugins)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
-        .add_plugin(RapierDebugRenderPlugin::default())
-        .add_plugin(ShapePlugin)
-        .add_plugin(PlayerPlugin)
-        .add_plugin(CornerPlugin)
-        .add_plugin(BarrierPlugin)
-        .add_plugin(BallPlugin)
-        .add_startup_system(setup_graphics)
-        .add_startup_system(setup_physic)
-        .add_startup_system(setup_arena)
-        .run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args
+        .iter()
+        .any(|arg| arg == "--headless" || arg == "--check-determinism")
+    {
+        match HeadlessConfig::from_args(&args) {
+            Ok(config) => headless::run(config),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut app = App::new();
+
+    // Replay a given match with --seed <seed>
+    if let Some(seed) = arg_value(&args, "--seed") {
+        match seed.parse() {
+            Ok(seed) => app.insert_resource(GameSeed(seed)),
+            Err(_) => {
+                eprintln!("invalid seed {}", seed);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // Play in another arena with --arena <file>, relative to the assets folder
+    let arena_file = arg_value(&args, "--arena").map_or(DEFAULT_ARENA_FILE, String::as_str);
+    let arena_config = match ArenaConfig::load(arena_file) {
+        Ok(arena_config) => arena_config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Pick the rules preset with --rules classic|chaos|practice, or a rules file
+    let rules_preset = arg_value(&args, "--rules").map_or(DEFAULT_RULES_PRESET, String::as_str);
+    let rules = match GameRules::load(rules_preset) {
+        Ok(rules) => rules,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Bindings saved from the controls menu, a broken file falling back to the defaults
+    let controls = Controls::load().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        Controls::default()
+    });
+
+    app.insert_resource(Msaa { samples: 4 })
+        .insert_resource(controls)
+        .insert_resource(arena_config)
+        .insert_resource(rules)
+        .insert_resource(RulesPreset(rules_preset.to_string()))
+        .insert_resource(ArenaFile(arena_file.to_string()))
+        .insert_resource(MatchSetup {
+            players: vec![
+                PlayerSetup {
+                    side: Side::Bottom,
+                    controller: Controller::Human(InputDevice::Keyboard(KeyboardLayout::Arrows)),
+                },
+                PlayerSetup {
+                    side: Side::Right,
+                    controller: Controller::Cpu(Difficulty::Normal),
+                },
+                PlayerSetup {
+                    side: Side::Top,
+                    controller: Controller::Cpu(Difficulty::Normal),
+                },
+                PlayerSetup {
+                    side: Side::Left,
+                    controller: Controller::Cpu(Difficulty::Normal),
+                },
+            ],
+        });
+
+    // Save the match to a file with --record <file>, play it back with --replay <file>
+    if let Some(path) = arg_value(&args, "--record") {
+        app.insert_resource(ReplayRecorder::new(path.into()));
+    }
+    if let Some(path) = arg_value(&args, "--replay") {
+        match Replay::load(path.as_ref()) {
+            Ok(replay) => replay::insert_replay(&mut app, replay),
+            Err(err) => {
+                eprintln!("failed to load replay {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let window_size = app.world.resource::<ArenaConfig>().window_size();
+    app.insert_resource(WindowDescriptor {
+        title: "Crash Ball".to_string(),
+        width: window_size.x,
+        height: window_size.y,
+        ..Default::default()
+    })
+    // Level designers can edit the arena file while the game runs
+    .insert_resource(AssetServerSettings {
+        watch_for_changes: true,
+        ..Default::default()
+    })
+    .add_plugins(DefaultPlugins)
+    .add_plugin(RapierDebugRenderPlugin::default())
+    .add_plugin(ShapePlugin)
+    .add_plugins(GameplayPlugins)
+    .add_plugin(RealTimeTickPlugin)
+    .add_plugin(ArenaHotReloadPlugin)
+    .add_plugin(OverlayPlugin)
+    .add_plugin(HudPlugin)
+    .add_plugin(MenuPlugin)
+    .add_startup_system(setup_graphics)
+    .add_startup_system(setup_physic)
+    .add_startup_system(setup_arena)
+    .run();
+}
+
+/// Value following `flag` on the command line
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    let idx = args.iter().position(|arg| arg == flag)?;
+    args.get(idx + 1)
 }
 
 fn setup_graphics(mut commands: Commands) {
@@ -64,20 +239,16 @@ fn setup_physic(mut commands: Commands, mut rapier_config: ResMut<RapierConfigur
     // Set gravity to 0.0 and spawn camera.
     rapier_config.gravity = Vec2::ZERO;
     rapier_config.physics_pipeline_active = true;
+
+    // One physics step per tick, the gameplay systems use the same TIME_STEP
+    rapier_config.timestep_mode = TimestepMode::Fixed {
+        dt: TIME_STEP,
+        substeps: 1,
+    };
 }
 
-fn setup_arena(mut commands: Commands, mut windows: ResMut<Windows>) {
-    // capture window size
+fn setup_arena(mut windows: ResMut<Windows>) {
     let mut window = windows.get_primary_mut().unwrap();
-    let (win_w, win_h) = (window.width(), window.height());
-
-    let arena_info = ArenaInfo(Rectangle {
-        x: -win_w / 2.0 + 50.0,
-        y: -win_h / 2.0 + 50.0,
-        w: win_w - 100.0,
-        h: win_h - 100.0,
-    });
-    commands.insert_resource(arena_info);
 
     // position window (temp for debug)
     window.set_position(IVec2::new(1700, 0));
@@ -0,0 +1,1338 @@
+use crate::{
+    ai::Difficulty,
+    arena::{ArenaConfig, ArenaFile},
+    controls::Controls,
+    game::{in_state, GameState, MatchSetup},
+    player::{
+        player_color, Controller, InputDevice, KeyboardLayout, Player, PlayerAction, PlayerSetup,
+    },
+    rules::{GameRules, RulesPreset},
+    tick::TickApp,
+    util::Side,
+    ArenaInfo,
+};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::{axislike::AxisType, prelude::*, user_input::InputKind};
+
+/// Main menu picking the players, the arena and the rules before starting a
+/// match, pause menu, and controls screen shared by both
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(InputManagerPlugin::<MenuAction>::default())
+            .init_resource::<PauseMenu>()
+            .init_resource::<ControlsMenu>()
+            .add_startup_system(menu_spawn_system)
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(in_state(GameState::Menu))
+                    .with_system(controls_menu_system.before(main_menu_system))
+                    .with_system(lobby_join_system.before(main_menu_system))
+                    .with_system(main_menu_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Paused).with_system(pause_menu_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Playing).with_system(pause_notice_clear_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_resume(GameState::Playing).with_system(pause_notice_clear_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Menu).with_system(pause_notice_clear_system),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(in_state(GameState::Paused))
+                    .with_system(controls_menu_system.before(pause_menu_system))
+                    .with_system(pause_menu_system),
+            )
+            .add_system(gamepad_connection_system)
+            .add_system(menu_text_system);
+    }
+}
+
+const MENU_FONT_SIZE: f32 = 26.0;
+const MENU_TITLE_FONT_SIZE: f32 = 56.0;
+const MENU_HINT_FONT_SIZE: f32 = 16.0;
+const MENU_SELECTED_COLOR: Color = Color::YELLOW;
+const MENU_DIM_COLOR: Color = Color::GRAY;
+
+#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub enum MenuAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+impl MenuAction {
+    fn default_key_map() -> InputMap<MenuAction> {
+        let mut input_map = InputMap::default();
+
+        input_map.insert(KeyCode::Up, MenuAction::Up);
+        input_map.insert(GamepadButtonType::DPadUp, MenuAction::Up);
+
+        input_map.insert(KeyCode::Down, MenuAction::Down);
+        input_map.insert(GamepadButtonType::DPadDown, MenuAction::Down);
+
+        input_map.insert(KeyCode::Left, MenuAction::Left);
+        input_map.insert(GamepadButtonType::DPadLeft, MenuAction::Left);
+
+        input_map.insert(KeyCode::Right, MenuAction::Right);
+        input_map.insert(GamepadButtonType::DPadRight, MenuAction::Right);
+
+        input_map.insert(KeyCode::Return, MenuAction::Select);
+        input_map.insert(GamepadButtonType::South, MenuAction::Select);
+
+        input_map.insert(KeyCode::Back, MenuAction::Back);
+        input_map.insert(GamepadButtonType::East, MenuAction::Back);
+
+        input_map
+    }
+}
+
+/// Entries of the main menu, from top to bottom
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MainMenuItem {
+    Player(Side),
+    Arena,
+    Rules,
+    Controls,
+    Start,
+}
+
+const MAIN_MENU_ITEMS: [MainMenuItem; 8] = [
+    MainMenuItem::Player(Side::Bottom),
+    MainMenuItem::Player(Side::Right),
+    MainMenuItem::Player(Side::Top),
+    MainMenuItem::Player(Side::Left),
+    MainMenuItem::Arena,
+    MainMenuItem::Rules,
+    MainMenuItem::Controls,
+    MainMenuItem::Start,
+];
+
+/// Choices of the main menu, turned into the match resources on start
+struct MainMenu {
+    selected: usize,
+    /// Controller of each side of `Side::ALL`, the side is a wall without one
+    controllers: [Option<Controller>; 4],
+    arenas: Vec<String>,
+    arena: usize,
+    presets: Vec<String>,
+    preset: usize,
+    /// Why the match could not start
+    error: Option<String>,
+    /// Playing back a replay, whose setup can't be changed
+    replay: bool,
+    /// Start the match again as soon as the menu is entered
+    restart: bool,
+}
+
+impl MainMenu {
+    fn new(match_setup: &MatchSetup, arena_file: &ArenaFile, rules_preset: &RulesPreset) -> Self {
+        let controllers = Side::ALL.map(|side| {
+            match_setup
+                .players
+                .iter()
+                .find(|setup| setup.side == side)
+                .map(|setup| setup.controller)
+        });
+
+        // Files given on the command line may live outside the usual folders
+        let mut arenas = ArenaConfig::files();
+        let arena = position_or_push(&mut arenas, &arena_file.0);
+        let mut presets = GameRules::presets();
+        let preset = position_or_push(&mut presets, &rules_preset.0);
+
+        Self {
+            selected: MAIN_MENU_ITEMS.len() - 1,
+            controllers,
+            arenas,
+            arena,
+            presets,
+            preset,
+            error: None,
+            replay: controllers.contains(&Some(Controller::Replay)),
+            restart: false,
+        }
+    }
+
+    fn item(&self) -> MainMenuItem {
+        MAIN_MENU_ITEMS[self.selected]
+    }
+
+    /// Devices of the human players, but the one on `except`
+    fn devices(&self, except: Option<Side>) -> Vec<InputDevice> {
+        Side::ALL
+            .iter()
+            .zip(self.controllers)
+            .filter(|(side, _)| Some(**side) != except)
+            .filter_map(|(_, controller)| match controller {
+                Some(Controller::Human(device)) => Some(device),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Give `device` to the first side without a player, or else played by the
+    /// CPU
+    fn join(&mut self, device: InputDevice) {
+        let idx = self
+            .controllers
+            .iter()
+            .position(Option::is_none)
+            .or_else(|| {
+                self.controllers
+                    .iter()
+                    .position(|controller| matches!(controller, Some(Controller::Cpu(_))))
+            });
+
+        if let Some(idx) = idx {
+            self.controllers[idx] = Some(Controller::Human(device));
+            self.error = None;
+        }
+    }
+
+    fn match_setup(&self) -> MatchSetup {
+        MatchSetup {
+            players: Side::ALL
+                .iter()
+                .zip(self.controllers)
+                .filter_map(|(side, controller)| {
+                    Some(PlayerSetup {
+                        side: *side,
+                        controller: controller?,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn label(&self, item: MainMenuItem) -> String {
+        match item {
+            MainMenuItem::Player(side) => {
+                let controller = self.controllers[side_index(side)];
+                format!("{:?}: {}", side, controller_label(controller))
+            }
+            MainMenuItem::Arena => format!(
+                "Arena: {}",
+                file_stem(&self.arenas[self.arena], "arenas/", ".arena.ron")
+            ),
+            MainMenuItem::Rules => format!(
+                "Rules: {}",
+                file_stem(&self.presets[self.preset], "rules/", ".rules.ron")
+            ),
+            MainMenuItem::Controls => "Controls".to_string(),
+            MainMenuItem::Start => "Start".to_string(),
+        }
+    }
+
+    fn color(&self, item: MainMenuItem) -> Color {
+        match item {
+            MainMenuItem::Player(side) => match self.controllers[side_index(side)] {
+                Some(_) => player_color(side),
+                None => MENU_DIM_COLOR,
+            },
+            _ => Color::WHITE,
+        }
+    }
+
+    fn lines(&self) -> Vec<MenuLine> {
+        let mut lines = vec![MenuLine::title("CRASH BALL")];
+
+        if self.replay {
+            lines.push(MenuLine::entry(
+                "Replay of a recorded match".to_string(),
+                false,
+                Color::WHITE,
+            ));
+        } else {
+            lines.extend(MAIN_MENU_ITEMS.iter().enumerate().map(|(idx, item)| {
+                MenuLine::entry(self.label(*item), idx == self.selected, self.color(*item))
+            }));
+        }
+
+        lines.push(match &self.error {
+            Some(error) => MenuLine::hint(error, Color::ORANGE_RED),
+            None if self.replay => MenuLine::hint("Enter: start", MENU_DIM_COLOR),
+            None => {
+                let join_keys: Vec<String> = KeyboardLayout::ALL
+                    .iter()
+                    .map(|layout| format!("{:?}", layout.join_key()))
+                    .collect();
+                let join = format!("{} or Start on a gamepad: join", join_keys.join("/"));
+                MenuLine::hint(
+                    &format!(
+                        "Up/Down: choose - Left/Right: change - Enter: start\n{}",
+                        join
+                    ),
+                    MENU_DIM_COLOR,
+                )
+            }
+        });
+        lines
+    }
+}
+
+/// Entries of the pause menu
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PauseMenuItem {
+    Resume,
+    Restart,
+    Settings,
+    Quit,
+}
+
+const PAUSE_MENU_ITEMS: [PauseMenuItem; 4] = [
+    PauseMenuItem::Resume,
+    PauseMenuItem::Restart,
+    PauseMenuItem::Settings,
+    PauseMenuItem::Quit,
+];
+
+/// Entries of the settings screen of the pause menu
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SettingsItem {
+    PhysicsDebug,
+    Controls,
+    Back,
+}
+
+const SETTINGS_ITEMS: [SettingsItem; 3] = [
+    SettingsItem::PhysicsDebug,
+    SettingsItem::Controls,
+    SettingsItem::Back,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum PauseScreen {
+    #[default]
+    Main,
+    Settings,
+}
+
+#[derive(Default)]
+struct PauseMenu {
+    screen: PauseScreen,
+    selected: usize,
+    /// What happened to the gamepads while paused
+    notice: Option<String>,
+}
+
+impl PauseMenu {
+    fn len(&self) -> usize {
+        match self.screen {
+            PauseScreen::Main => PAUSE_MENU_ITEMS.len(),
+            PauseScreen::Settings => SETTINGS_ITEMS.len(),
+        }
+    }
+
+    fn lines(&self, debug_render: &DebugRenderContext) -> Vec<MenuLine> {
+        let entry = |idx: usize, label: &str| {
+            MenuLine::entry(label.to_string(), idx == self.selected, Color::WHITE)
+        };
+
+        match self.screen {
+            PauseScreen::Main => {
+                let mut lines = vec![MenuLine::title("PAUSED")];
+                lines.extend(PAUSE_MENU_ITEMS.iter().enumerate().map(|(idx, item)| {
+                    entry(
+                        idx,
+                        match item {
+                            PauseMenuItem::Resume => "Resume",
+                            PauseMenuItem::Restart => "Restart match",
+                            PauseMenuItem::Settings => "Settings",
+                            PauseMenuItem::Quit => "Quit match",
+                        },
+                    )
+                }));
+                lines.push(match &self.notice {
+                    Some(notice) => MenuLine::hint(notice, Color::ORANGE_RED),
+                    None => MenuLine::hint("Enter: select - Escape: resume", MENU_DIM_COLOR),
+                });
+                lines
+            }
+            PauseScreen::Settings => {
+                let mut lines = vec![MenuLine::title("SETTINGS")];
+                lines.extend(
+                    SETTINGS_ITEMS
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, item)| match item {
+                            SettingsItem::PhysicsDebug => entry(
+                                idx,
+                                if debug_render.enabled {
+                                    "Physics debug: on"
+                                } else {
+                                    "Physics debug: off"
+                                },
+                            ),
+                            SettingsItem::Controls => entry(idx, "Controls"),
+                            SettingsItem::Back => entry(idx, "Back"),
+                        }),
+                );
+                lines.push(MenuLine::hint(
+                    "Left/Right: change - Backspace: back",
+                    MENU_DIM_COLOR,
+                ));
+                lines
+            }
+        }
+    }
+}
+
+/// Entries of the controls screen
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ControlsItem {
+    /// Player whose controls are shown
+    Player,
+    Action(PlayerAction),
+    Reset,
+    Back,
+}
+
+const CONTROLS_ITEMS: [ControlsItem; 9] = [
+    ControlsItem::Player,
+    ControlsItem::Action(PlayerAction::Move),
+    ControlsItem::Action(PlayerAction::MoveLeft),
+    ControlsItem::Action(PlayerAction::MoveRight),
+    ControlsItem::Action(PlayerAction::Accelerate),
+    ControlsItem::Action(PlayerAction::Energy),
+    ControlsItem::Action(PlayerAction::Pause),
+    ControlsItem::Reset,
+    ControlsItem::Back,
+];
+
+/// Screen rebinding the actions of each player, over the main or pause menu
+#[derive(Default)]
+struct ControlsMenu {
+    open: bool,
+    selected: usize,
+    /// Index in `Side::ALL` of the player whose controls are shown
+    side: usize,
+    /// Device of each human player of `Side::ALL`
+    devices: [Option<InputDevice>; 4],
+    /// Action waiting for its new input
+    rebinding: Option<PlayerAction>,
+    /// Why the controls could not be saved
+    error: Option<String>,
+}
+
+impl ControlsMenu {
+    /// Show the controls of the first human player of `match_setup`
+    fn open(&mut self, match_setup: &MatchSetup) {
+        let side = match_setup
+            .players
+            .iter()
+            .find(|setup| matches!(setup.controller, Controller::Human(_)))
+            .map_or(Side::Bottom, |setup| setup.side);
+        *self = ControlsMenu {
+            open: true,
+            side: side_index(side),
+            devices: Side::ALL.map(|side| match_setup.device(side)),
+            ..Default::default()
+        };
+    }
+
+    fn side(&self) -> Side {
+        Side::ALL[self.side]
+    }
+
+    /// Keys bound by default for the side shown
+    fn layout(&self) -> KeyboardLayout {
+        self.devices[self.side]
+            .map(|device| device.layout())
+            .unwrap_or_default()
+    }
+
+    fn input_map(&self, controls: &Controls) -> InputMap<PlayerAction> {
+        controls.input_map(self.side(), self.layout())
+    }
+
+    /// Why `input` can't be bound to `action` of the side shown, the inputs
+    /// pausing the match and the keys of the other players being kept for them
+    fn conflict(
+        &self,
+        controls: &Controls,
+        action: PlayerAction,
+        input: InputKind,
+    ) -> Option<String> {
+        let input = UserInput::from(input);
+        let pause_key_map = PlayerAction::pause_key_map();
+        if pause_key_map.get(PlayerAction::Pause).contains(&input) {
+            return match action {
+                PlayerAction::Pause => None,
+                _ => Some(format!("{} pauses the match", input_label(&input))),
+            };
+        }
+
+        // Gamepads are not shared, only the keyboard is
+        Side::ALL
+            .into_iter()
+            .zip(self.devices)
+            .filter(|(side, _)| *side != self.side())
+            .find_map(|(side, device)| match device {
+                Some(device @ InputDevice::Keyboard(layout)) => device
+                    .input_map(&controls.input_map(side, layout))
+                    .iter()
+                    .any(|(inputs, _)| inputs.contains(&input))
+                    .then(|| format!("{} is used by {:?}", input_label(&input), side)),
+                _ => None,
+            })
+    }
+
+    fn lines(&self, controls: &Controls) -> Vec<MenuLine> {
+        let side = self.side();
+        let input_map = self.input_map(controls);
+
+        let mut lines = vec![MenuLine::title("CONTROLS")];
+        lines.extend(CONTROLS_ITEMS.iter().enumerate().map(|(idx, item)| {
+            let (label, color) = match item {
+                ControlsItem::Player => (
+                    format!("Player: {:?} ({})", side, layout_label(self.layout())),
+                    player_color(side),
+                ),
+                ControlsItem::Action(action) if self.rebinding == Some(*action) => {
+                    let input = match action {
+                        PlayerAction::Move => "a stick",
+                        _ => "a key or button",
+                    };
+                    (
+                        format!("{}: move {}...", action_label(*action), input),
+                        Color::WHITE,
+                    )
+                }
+                ControlsItem::Action(action) => (
+                    format!(
+                        "{}: {}",
+                        action_label(*action),
+                        bindings_label(&input_map, *action)
+                    ),
+                    Color::WHITE,
+                ),
+                ControlsItem::Reset => ("Reset to defaults".to_string(), Color::WHITE),
+                ControlsItem::Back => ("Back".to_string(), Color::WHITE),
+            };
+            MenuLine::entry(label, idx == self.selected, color)
+        }));
+
+        lines.push(match &self.error {
+            Some(error) => MenuLine::hint(error, Color::ORANGE_RED),
+            None if self.rebinding.is_some() => MenuLine::hint("Backspace: cancel", MENU_DIM_COLOR),
+            None => MenuLine::hint(
+                "Enter: rebind - Left/Right: player - Backspace: back",
+                MENU_DIM_COLOR,
+            ),
+        });
+        lines
+    }
+}
+
+/// Line of a menu, drawn as a section of the menu text
+#[derive(PartialEq)]
+struct MenuLine {
+    value: String,
+    font_size: f32,
+    color: Color,
+}
+
+impl MenuLine {
+    fn title(value: &str) -> Self {
+        Self {
+            value: format!("{}\n", value),
+            font_size: MENU_TITLE_FONT_SIZE,
+            color: Color::WHITE,
+        }
+    }
+
+    fn entry(label: String, selected: bool, color: Color) -> Self {
+        let (value, color) = if selected {
+            (format!("\n< {} >", label), MENU_SELECTED_COLOR)
+        } else {
+            (format!("\n{}", label), color)
+        };
+
+        Self {
+            value,
+            font_size: MENU_FONT_SIZE,
+            color,
+        }
+    }
+
+    fn hint(value: &str, color: Color) -> Self {
+        Self {
+            value: format!("\n\n{}", value),
+            font_size: MENU_HINT_FONT_SIZE,
+            color,
+        }
+    }
+
+    fn matches(&self, section: &TextSection) -> bool {
+        self.value == section.value
+            && self.font_size == section.style.font_size
+            && self.color == section.style.color
+    }
+}
+
+/// Text of the menu being shown
+#[derive(Component)]
+struct MenuText;
+
+fn side_index(side: Side) -> usize {
+    Side::ALL.iter().position(|other| *other == side).unwrap()
+}
+
+fn position_or_push(values: &mut Vec<String>, value: &str) -> usize {
+    match values.iter().position(|other| other == value) {
+        Some(idx) => idx,
+        None => {
+            values.push(value.to_string());
+            values.len() - 1
+        }
+    }
+}
+
+fn file_stem<'a>(file: &'a str, prefix: &str, extension: &str) -> &'a str {
+    file.strip_prefix(prefix)
+        .and_then(|file| file.strip_suffix(extension))
+        .unwrap_or(file)
+}
+
+fn layout_label(layout: KeyboardLayout) -> &'static str {
+    match layout {
+        KeyboardLayout::Arrows => "arrows",
+        KeyboardLayout::Wasd => "WASD",
+        KeyboardLayout::Ijkl => "IJKL",
+    }
+}
+
+fn controller_label(controller: Option<Controller>) -> String {
+    match controller {
+        None => "-".to_string(),
+        Some(Controller::Human(InputDevice::Keyboard(layout))) => {
+            format!("Player ({})", layout_label(layout))
+        }
+        Some(Controller::Human(InputDevice::Gamepad(id))) => {
+            format!("Player (gamepad {})", id + 1)
+        }
+        Some(Controller::Cpu(difficulty)) => {
+            format!("CPU ({})", format!("{:?}", difficulty).to_lowercase())
+        }
+        Some(Controller::Replay) => "Replay".to_string(),
+    }
+}
+
+/// Controllers a side can be given, starting with none, leaving out the
+/// devices `taken` by the other sides
+fn controller_choices(gamepads: &Gamepads, taken: &[InputDevice]) -> Vec<Option<Controller>> {
+    let devices = KeyboardLayout::ALL
+        .into_iter()
+        .map(InputDevice::Keyboard)
+        .chain(
+            gamepads
+                .iter()
+                .map(|gamepad| InputDevice::Gamepad(gamepad.id)),
+        );
+
+    let mut choices = vec![None];
+    choices.extend(
+        devices
+            .filter(|device| !taken.contains(device))
+            .map(|device| Some(Controller::Human(device))),
+    );
+    choices.extend(
+        [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard]
+            .map(|difficulty| Some(Controller::Cpu(difficulty))),
+    );
+    choices
+}
+
+fn action_label(action: PlayerAction) -> &'static str {
+    match action {
+        PlayerAction::Move => "Move",
+        PlayerAction::MoveLeft => "Move left",
+        PlayerAction::MoveRight => "Move right",
+        PlayerAction::Accelerate => "Accelerate",
+        PlayerAction::Energy => "Energy",
+        PlayerAction::Pause => "Pause",
+    }
+}
+
+fn input_label(input: &UserInput) -> String {
+    match input {
+        UserInput::Single(InputKind::Keyboard(key)) => format!("{:?}", key),
+        UserInput::Single(InputKind::GamepadButton(button)) => format!("{:?}", button),
+        UserInput::Single(InputKind::SingleAxis(SingleAxis {
+            axis_type: AxisType::Gamepad(axis),
+            ..
+        })) => format!("{:?}", axis),
+        input => format!("{:?}", input),
+    }
+}
+
+fn bindings_label(input_map: &InputMap<PlayerAction>, action: PlayerAction) -> String {
+    let labels: Vec<String> = input_map.get(action).iter().map(input_label).collect();
+
+    if labels.is_empty() {
+        "-".to_string()
+    } else {
+        labels.join(", ")
+    }
+}
+
+/// Key or gamepad button pressed in this frame
+fn pressed_button(
+    keyboard: &Input<KeyCode>,
+    gamepad_buttons: &Input<GamepadButton>,
+) -> Option<InputKind> {
+    keyboard
+        .get_just_pressed()
+        .next()
+        .map(|key| InputKind::Keyboard(*key))
+        .or_else(|| {
+            gamepad_buttons
+                .get_just_pressed()
+                .next()
+                .map(|button| InputKind::GamepadButton(button.button_type))
+        })
+}
+
+/// Gamepad axis pushed at least halfway by any gamepad
+fn pushed_axis(gamepads: &Gamepads, gamepad_axes: &Axis<GamepadAxis>) -> Option<InputKind> {
+    const AXES: [GamepadAxisType; 6] = [
+        GamepadAxisType::LeftStickX,
+        GamepadAxisType::LeftStickY,
+        GamepadAxisType::RightStickX,
+        GamepadAxisType::RightStickY,
+        GamepadAxisType::LeftZ,
+        GamepadAxisType::RightZ,
+    ];
+
+    gamepads.iter().find_map(|gamepad| {
+        AXES.into_iter()
+            .find(|axis| {
+                gamepad_axes
+                    .get(GamepadAxis::new(*gamepad, *axis))
+                    .map_or(false, |value| value.abs() > 0.5)
+            })
+            .map(|axis| {
+                InputKind::SingleAxis(SingleAxis::symmetric(axis, DualAxis::DEFAULT_DEADZONE))
+            })
+    })
+}
+
+/// Bind `input` to `action` instead of the other inputs of its device, taking
+/// it away from the other actions
+fn rebind(input_map: &mut InputMap<PlayerAction>, action: PlayerAction, input: InputKind) {
+    let is_keyboard =
+        |input: &UserInput| matches!(input, UserInput::Single(InputKind::Keyboard(_)));
+    let keyboard = is_keyboard(&input.into());
+
+    let kept: Vec<UserInput> = input_map
+        .get(action)
+        .iter()
+        .filter(|other| is_keyboard(other) != keyboard)
+        .cloned()
+        .collect();
+
+    input_map.clear_action(action);
+    for other in PlayerAction::variants() {
+        input_map.remove(other, input);
+    }
+    for other in kept {
+        input_map.insert(other, action);
+    }
+    input_map.insert(input, action);
+}
+
+/// Index `step` entries away from `idx`, wrapping around `len` entries
+fn cycle(idx: usize, len: usize, step: isize) -> usize {
+    (idx as isize + step).rem_euclid(len as isize) as usize
+}
+
+fn menu_spawn_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    match_setup: Res<MatchSetup>,
+    arena_file: Res<ArenaFile>,
+    rules_preset: Res<RulesPreset>,
+) {
+    commands.insert_resource(MainMenu::new(&match_setup, &arena_file, &rules_preset));
+
+    commands.spawn_bundle(InputManagerBundle::<MenuAction> {
+        action_state: ActionState::default(),
+        input_map: MenuAction::default_key_map(),
+    });
+
+    // Players without a paddle can still pause the match
+    commands.spawn_bundle(InputManagerBundle::<PlayerAction> {
+        action_state: ActionState::default(),
+        input_map: PlayerAction::pause_key_map(),
+    });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/DejaVuSans.ttf"),
+                            font_size: MENU_FONT_SIZE,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_text_alignment(TextAlignment::CENTER),
+                )
+                .insert(MenuText);
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn main_menu_system(
+    mut menu: ResMut<MainMenu>,
+    mut controls_menu: ResMut<ControlsMenu>,
+    mut state: ResMut<State<GameState>>,
+    mut commands: Commands,
+    mut arena_info: ResMut<ArenaInfo>,
+    mut match_setup: ResMut<MatchSetup>,
+    gamepads: Res<Gamepads>,
+    action_query: Query<&ActionState<MenuAction>>,
+) {
+    let action_state = match action_query.get_single() {
+        Ok(action_state) => action_state,
+        Err(_) => return,
+    };
+
+    if menu.restart {
+        menu.restart = false;
+        let _ = state.set(GameState::Countdown);
+        return;
+    }
+
+    if menu.replay {
+        if action_state.just_pressed(MenuAction::Select) {
+            let _ = state.set(GameState::Countdown);
+        }
+        return;
+    }
+
+    if controls_menu.open {
+        return;
+    }
+    if menu.item() == MainMenuItem::Controls && action_state.just_pressed(MenuAction::Select) {
+        controls_menu.open(&menu.match_setup());
+        return;
+    }
+
+    if action_state.just_pressed(MenuAction::Up) {
+        menu.selected = cycle(menu.selected, MAIN_MENU_ITEMS.len(), -1);
+    }
+    if action_state.just_pressed(MenuAction::Down) {
+        menu.selected = cycle(menu.selected, MAIN_MENU_ITEMS.len(), 1);
+    }
+
+    // Selecting an entry other than Start changes it too
+    let forward = action_state.just_pressed(MenuAction::Right)
+        || (action_state.just_pressed(MenuAction::Select) && menu.item() != MainMenuItem::Start);
+    let step = if action_state.just_pressed(MenuAction::Left) {
+        -1
+    } else if forward {
+        1
+    } else {
+        0
+    };
+
+    if step != 0 {
+        menu.error = None;
+        match menu.item() {
+            MainMenuItem::Player(side) => {
+                let taken = menu.devices(Some(side));
+                let choices = controller_choices(&gamepads, &taken);
+                let controller = &mut menu.controllers[side_index(side)];
+                // A gamepad unplugged since it was picked starts over
+                let idx = choices
+                    .iter()
+                    .position(|choice| choice == controller)
+                    .unwrap_or(0);
+                *controller = choices[cycle(idx, choices.len(), step)];
+            }
+            MainMenuItem::Arena => menu.arena = cycle(menu.arena, menu.arenas.len(), step),
+            MainMenuItem::Rules => menu.preset = cycle(menu.preset, menu.presets.len(), step),
+            MainMenuItem::Controls | MainMenuItem::Start => {}
+        }
+    }
+
+    if menu.item() != MainMenuItem::Start || !action_state.just_pressed(MenuAction::Select) {
+        return;
+    }
+
+    let arena_config = match ArenaConfig::load(&menu.arenas[menu.arena]) {
+        Ok(arena_config) => arena_config,
+        Err(err) => {
+            menu.error = Some(err);
+            return;
+        }
+    };
+    let rules = match GameRules::load(&menu.presets[menu.preset]) {
+        Ok(rules) => rules,
+        Err(err) => {
+            menu.error = Some(err);
+            return;
+        }
+    };
+
+    let setup = menu.match_setup();
+    let players = setup
+        .players
+        .iter()
+        .filter(|player| arena_config.goals.contains(&player.side))
+        .count();
+    if players < 2 {
+        menu.error = Some("Two players are needed on the sides open in this arena".to_string());
+        return;
+    }
+
+    // The round is set up as soon as the state changes, before the arena
+    // plugin catches up with the new config
+    *arena_info = ArenaInfo::centered(arena_config.width, arena_config.height);
+    *match_setup = setup;
+    commands.insert_resource(arena_config);
+    commands.insert_resource(rules);
+    commands.insert_resource(ArenaFile(menu.arenas[menu.arena].clone()));
+    commands.insert_resource(RulesPreset(menu.presets[menu.preset].clone()));
+    let _ = state.set(GameState::Countdown);
+}
+
+/// Give the keyboard layout or the gamepad whose button is pressed to a side
+fn lobby_join_system(
+    mut menu: ResMut<MainMenu>,
+    controls_menu: Res<ControlsMenu>,
+    keyboard: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+) {
+    if menu.replay || controls_menu.open {
+        return;
+    }
+
+    let layouts = KeyboardLayout::ALL
+        .into_iter()
+        .filter(|layout| keyboard.just_pressed(layout.join_key()))
+        .map(InputDevice::Keyboard);
+    let gamepads = gamepad_buttons
+        .get_just_pressed()
+        .filter(|button| {
+            button.button_type == GamepadButtonType::Start && gamepads.contains(&button.gamepad)
+        })
+        .map(|button| InputDevice::Gamepad(button.gamepad.id));
+
+    for device in layouts.chain(gamepads) {
+        if !menu.devices(None).contains(&device) {
+            menu.join(device);
+        }
+    }
+}
+
+fn pause_menu_reset_system(
+    mut pause_menu: ResMut<PauseMenu>,
+    mut controls_menu: ResMut<ControlsMenu>,
+) {
+    // The notice of the gamepad which paused the match is kept
+    pause_menu.screen = PauseScreen::Main;
+    pause_menu.selected = 0;
+    controls_menu.open = false;
+}
+
+/// Forget what happened to the gamepads once the pause they caused is over
+fn pause_notice_clear_system(mut pause_menu: ResMut<PauseMenu>) {
+    pause_menu.notice = None;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pause_menu_system(
+    mut pause_menu: ResMut<PauseMenu>,
+    mut main_menu: ResMut<MainMenu>,
+    mut controls_menu: ResMut<ControlsMenu>,
+    match_setup: Res<MatchSetup>,
+    mut state: ResMut<State<GameState>>,
+    mut debug_render: ResMut<DebugRenderContext>,
+    action_query: Query<&ActionState<MenuAction>>,
+    mut player_action_query: Query<&mut ActionState<PlayerAction>>,
+) {
+    let action_state = match action_query.get_single() {
+        Ok(action_state) => action_state,
+        Err(_) => return,
+    };
+    if controls_menu.open {
+        return;
+    }
+
+    let mut pause_pressed = false;
+    for mut player_action_state in player_action_query.iter_mut() {
+        if player_action_state.just_pressed(PlayerAction::Pause) {
+            // Not taken for another pause once playing again in this frame
+            player_action_state.consume(PlayerAction::Pause);
+            pause_pressed = true;
+        }
+    }
+    if pause_pressed
+        || (pause_menu.screen == PauseScreen::Main && action_state.just_pressed(MenuAction::Back))
+    {
+        let _ = state.pop();
+        return;
+    }
+
+    if action_state.just_pressed(MenuAction::Up) {
+        pause_menu.selected = cycle(pause_menu.selected, pause_menu.len(), -1);
+    }
+    if action_state.just_pressed(MenuAction::Down) {
+        pause_menu.selected = cycle(pause_menu.selected, pause_menu.len(), 1);
+    }
+
+    let select = action_state.just_pressed(MenuAction::Select);
+    match pause_menu.screen {
+        PauseScreen::Main if select => match PAUSE_MENU_ITEMS[pause_menu.selected] {
+            PauseMenuItem::Resume => {
+                let _ = state.pop();
+            }
+            PauseMenuItem::Restart => {
+                // Through the menu, resetting the match like a new one
+                main_menu.restart = true;
+                let _ = state.replace(GameState::Menu);
+            }
+            PauseMenuItem::Settings => {
+                pause_menu.screen = PauseScreen::Settings;
+                pause_menu.selected = 0;
+            }
+            PauseMenuItem::Quit => {
+                let _ = state.replace(GameState::Menu);
+            }
+        },
+        PauseScreen::Main => {}
+        PauseScreen::Settings => {
+            let change = select
+                || action_state.just_pressed(MenuAction::Left)
+                || action_state.just_pressed(MenuAction::Right);
+            let back = action_state.just_pressed(MenuAction::Back)
+                || (select && SETTINGS_ITEMS[pause_menu.selected] == SettingsItem::Back);
+
+            if back {
+                pause_menu.screen = PauseScreen::Main;
+                pause_menu.selected = PAUSE_MENU_ITEMS
+                    .iter()
+                    .position(|item| *item == PauseMenuItem::Settings)
+                    .unwrap();
+            } else if change {
+                match SETTINGS_ITEMS[pause_menu.selected] {
+                    SettingsItem::PhysicsDebug => debug_render.enabled = !debug_render.enabled,
+                    SettingsItem::Controls if select => controls_menu.open(&match_setup),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn controls_menu_system(
+    mut controls_menu: ResMut<ControlsMenu>,
+    mut controls: ResMut<Controls>,
+    keyboard: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    gamepads: Res<Gamepads>,
+    match_setup: Res<MatchSetup>,
+    mut action_query: Query<&mut ActionState<MenuAction>>,
+    mut player_query: Query<(&Player, &mut InputMap<PlayerAction>)>,
+) {
+    if !controls_menu.open {
+        return;
+    }
+    let mut action_state = match action_query.get_single_mut() {
+        Ok(action_state) => action_state,
+        Err(_) => return,
+    };
+    let side = controls_menu.side();
+    let mut changed = false;
+
+    if let Some(action) = controls_menu.rebinding {
+        let input = match action {
+            PlayerAction::Move => pushed_axis(&gamepads, &gamepad_axes),
+            _ => pressed_button(&keyboard, &gamepad_buttons),
+        };
+
+        if action_state.just_pressed(MenuAction::Back) {
+            controls_menu.rebinding = None;
+            controls_menu.error = None;
+        } else if let Some(input) = input {
+            // Waiting for another input after a refused one
+            controls_menu.error = controls_menu.conflict(&controls, action, input);
+            if controls_menu.error.is_none() {
+                let mut input_map = controls_menu.input_map(&controls);
+                rebind(&mut input_map, action, input);
+                controls.set_input_map(side, controls_menu.layout(), &input_map);
+                controls_menu.rebinding = None;
+                changed = true;
+            }
+        }
+    } else {
+        if action_state.just_pressed(MenuAction::Up) {
+            controls_menu.selected = cycle(controls_menu.selected, CONTROLS_ITEMS.len(), -1);
+        }
+        if action_state.just_pressed(MenuAction::Down) {
+            controls_menu.selected = cycle(controls_menu.selected, CONTROLS_ITEMS.len(), 1);
+        }
+
+        let select = action_state.just_pressed(MenuAction::Select);
+        let step = if action_state.just_pressed(MenuAction::Left) {
+            -1
+        } else if action_state.just_pressed(MenuAction::Right) || select {
+            1
+        } else {
+            0
+        };
+
+        match CONTROLS_ITEMS[controls_menu.selected] {
+            ControlsItem::Player if step != 0 => {
+                controls_menu.side = cycle(controls_menu.side, Side::ALL.len(), step);
+                controls_menu.error = None;
+            }
+            ControlsItem::Action(action) if select => controls_menu.rebinding = Some(action),
+            ControlsItem::Reset if select => {
+                controls.reset(side, controls_menu.layout());
+                changed = true;
+            }
+            _ => {}
+        }
+
+        let back = action_state.just_pressed(MenuAction::Back)
+            || (select && CONTROLS_ITEMS[controls_menu.selected] == ControlsItem::Back);
+        if back {
+            controls_menu.open = false;
+            // Left to the menu underneath otherwise, running next in this frame
+            action_state.consume(MenuAction::Back);
+            action_state.consume(MenuAction::Select);
+        }
+    }
+
+    if changed {
+        controls_menu.error = controls.save().err();
+
+        // Players of the current match keep their device
+        if let Some(device) = match_setup.device(side) {
+            for (_, mut input_map) in player_query
+                .iter_mut()
+                .filter(|(player, _)| player.side == side)
+            {
+                *input_map = device.input_map(&controls.input_map(side, device.layout()));
+            }
+        }
+    }
+}
+
+/// Pause the match when a player's gamepad is unplugged, and give the next
+/// gamepad plugged in to a player left without one
+#[allow(clippy::too_many_arguments)]
+fn gamepad_connection_system(
+    mut events: EventReader<GamepadEvent>,
+    gamepads: Res<Gamepads>,
+    mut state: ResMut<State<GameState>>,
+    mut match_setup: ResMut<MatchSetup>,
+    mut main_menu: Option<ResMut<MainMenu>>,
+    mut pause_menu: ResMut<PauseMenu>,
+    controls: Res<Controls>,
+    mut player_query: Query<(&Player, &mut InputMap<PlayerAction>)>,
+) {
+    // Notices are only shown by the pause menu
+    let mut paused = *state.current() == GameState::Paused;
+
+    for event in events.iter() {
+        let id = event.gamepad.id;
+        let player_side = |match_setup: &MatchSetup| {
+            Side::ALL
+                .into_iter()
+                .find(|side| match_setup.device(*side) == Some(InputDevice::Gamepad(id)))
+        };
+
+        let notice = match event.event_type {
+            GamepadEventType::Disconnected => match player_side(&match_setup) {
+                Some(side) => {
+                    if *state.current() == GameState::Playing
+                        && state.push(GameState::Paused).is_ok()
+                    {
+                        paused = true;
+                    }
+                    format!("Gamepad {} of {:?} unplugged", id + 1, side)
+                }
+                None => continue,
+            },
+            GamepadEventType::Connected => {
+                if let Some(side) = player_side(&match_setup) {
+                    format!("Gamepad {} of {:?} plugged back in", id + 1, side)
+                } else {
+                    // Unplugged gamepads may come back with another id
+                    let unplugged = |side: &Side| match match_setup.device(*side) {
+                        Some(InputDevice::Gamepad(other)) => {
+                            !gamepads.contains(&Gamepad { id: other })
+                        }
+                        _ => false,
+                    };
+                    let side = match Side::ALL.into_iter().find(unplugged) {
+                        Some(side) => side,
+                        None => continue,
+                    };
+
+                    let device = InputDevice::Gamepad(id);
+                    for setup in match_setup.players.iter_mut() {
+                        if setup.side == side {
+                            setup.controller = Controller::Human(device);
+                        }
+                    }
+                    if let Some(main_menu) = main_menu.as_mut() {
+                        main_menu.controllers[side_index(side)] = Some(Controller::Human(device));
+                    }
+                    for (_, mut input_map) in player_query
+                        .iter_mut()
+                        .filter(|(player, _)| player.side == side)
+                    {
+                        *input_map = device.input_map(&controls.input_map(side, device.layout()));
+                    }
+                    format!("Gamepad {} now plays {:?}", id + 1, side)
+                }
+            }
+            _ => continue,
+        };
+
+        if paused {
+            pause_menu.notice = Some(notice);
+        }
+    }
+}
+
+fn menu_text_system(
+    state: Res<State<GameState>>,
+    main_menu: Option<Res<MainMenu>>,
+    pause_menu: Res<PauseMenu>,
+    controls_menu: Res<ControlsMenu>,
+    controls: Res<Controls>,
+    debug_render: Res<DebugRenderContext>,
+    mut query: Query<&mut Text, With<MenuText>>,
+) {
+    let lines = match (state.current(), main_menu) {
+        (GameState::Menu | GameState::Paused, _) if controls_menu.open => {
+            controls_menu.lines(&controls)
+        }
+        (GameState::Menu, Some(main_menu)) if !main_menu.restart => main_menu.lines(),
+        (GameState::Paused, _) => pause_menu.lines(&debug_render),
+        // A single empty section, keeping hold of the font
+        _ => vec![MenuLine {
+            value: String::new(),
+            font_size: MENU_FONT_SIZE,
+            color: Color::WHITE,
+        }],
+    };
+
+    for mut text in query.iter_mut() {
+        let unchanged = text.sections.len() == lines.len()
+            && lines
+                .iter()
+                .zip(text.sections.iter())
+                .all(|(line, section)| line.matches(section));
+        if unchanged {
+            continue;
+        }
+
+        // Every section shares the font the text was spawned with
+        let font = match text.sections.first() {
+            Some(section) => section.style.font.clone(),
+            None => continue,
+        };
+        text.sections = lines
+            .iter()
+            .map(|line| {
+                TextSection::new(
+                    line.value.clone(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: line.font_size,
+                        color: line.color,
+                    },
+                )
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controls_menu() -> ControlsMenu {
+        ControlsMenu {
+            open: true,
+            devices: [
+                Some(InputDevice::Keyboard(KeyboardLayout::Arrows)),
+                Some(InputDevice::Keyboard(KeyboardLayout::Wasd)),
+                Some(InputDevice::Gamepad(0)),
+                None,
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pause_inputs_are_kept_for_pause() {
+        let menu = controls_menu();
+        let controls = Controls::default();
+
+        for input in [
+            InputKind::Keyboard(KeyCode::Escape),
+            InputKind::GamepadButton(GamepadButtonType::Start),
+        ] {
+            assert!(menu
+                .conflict(&controls, PlayerAction::Energy, input)
+                .is_some());
+            assert!(menu
+                .conflict(&controls, PlayerAction::Pause, input)
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn keys_of_other_keyboard_players_are_refused() {
+        let menu = controls_menu();
+        let controls = Controls::default();
+        let wasd_left = InputKind::Keyboard(KeyCode::A);
+        assert!(menu
+            .conflict(&controls, PlayerAction::MoveLeft, wasd_left)
+            .is_some());
+        // Gamepads are not shared
+        let button = InputKind::GamepadButton(GamepadButtonType::West);
+        assert!(menu
+            .conflict(&controls, PlayerAction::Energy, button)
+            .is_none());
+        // Nor the keys of nobody
+        let ijkl_left = InputKind::Keyboard(KeyCode::J);
+        assert!(menu
+            .conflict(&controls, PlayerAction::MoveLeft, ijkl_left)
+            .is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    game::{CountdownTimer, GameState, MatchScore},
+    pacing::Pacing,
+    rules::GameRules,
+};
+use bevy::prelude::*;
+
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(overlay_spawn_system)
+            .add_system(overlay_update_system);
+    }
+}
+
+const OVERLAY_FONT_SIZE: f32 = 48.0;
+
+#[derive(Component)]
+struct OverlayText;
+
+fn overlay_spawn_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/DejaVuSans.ttf"),
+                            font_size: OVERLAY_FONT_SIZE,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_text_alignment(TextAlignment::CENTER),
+                )
+                .insert(OverlayText);
+        });
+}
+
+fn overlay_update_system(
+    state: Res<State<GameState>>,
+    countdown: Res<CountdownTimer>,
+    match_score: Res<MatchScore>,
+    pacing: Res<Pacing>,
+    rules: Res<GameRules>,
+    mut query: Query<&mut Text, With<OverlayText>>,
+) {
+    let message = match state.current() {
+        // Drawn by the menus
+        GameState::Menu | GameState::Paused => String::new(),
+        GameState::Countdown => {
+            let remaining = countdown.0.duration() - countdown.0.elapsed();
+            format!("{}", remaining.as_secs_f32().ceil())
+        }
+        GameState::Playing if pacing.sudden_death => "SUDDEN DEATH".to_string(),
+        GameState::Playing => String::new(),
+        GameState::RoundOver => match match_score.last_winner {
+            Some(winner) => format!("{:?} wins the round", winner),
+            None => "Nobody wins the round".to_string(),
+        },
+        GameState::MatchOver => match match_score.match_winner(rules.rounds_to_win) {
+            Some(winner) => format!("{:?} wins the match\nEnter: rematch - Escape: menu", winner),
+            None => "Match over".to_string(),
+        },
+    };
+
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != message {
+            text.sections[0].value = message.clone();
+        }
+    }
+}
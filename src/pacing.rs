@@ -0,0 +1,235 @@
+use crate::{
+    ball::{Ball, BallState},
+    game::{GameState, GameplayStep},
+    player::Lives,
+    rules::{GameRules, PacingDriver, PacingPoint, PacingRules},
+    score::Score,
+    tick::TickApp,
+    TIME_STEP,
+};
+use bevy::prelude::*;
+
+pub struct PacingPlugin;
+
+impl Plugin for PacingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_event::<SuddenDeath>()
+            .init_resource::<Pacing>()
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Countdown).with_system(pacing_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        pacing_system
+                            .label(GameplayStep::Pacing)
+                            .after(GameplayStep::Player),
+                    )
+                    .with_system(
+                        sudden_death_system
+                            .label(GameplayStep::Pacing)
+                            .after(pacing_system),
+                    )
+                    .with_system(
+                        pacing_ball_speed_system
+                            .label(GameplayStep::Pacing)
+                            .after(sudden_death_system),
+                    ),
+            );
+    }
+}
+
+/// How far the current round has sped up
+pub struct Pacing {
+    /// Seconds played in the round
+    pub elapsed: f32,
+    /// Balls allowed on top of the ball count
+    pub extra_balls: u32,
+    /// Spawn frequency relative to the rules
+    pub spawn_rate: f32,
+    /// Ball speed relative to the rules
+    pub speed: f32,
+    pub sudden_death: bool,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            elapsed: 0.0,
+            extra_balls: 0,
+            spawn_rate: 1.0,
+            speed: 1.0,
+            sudden_death: false,
+        }
+    }
+}
+
+/// Sent when sudden death starts
+pub struct SuddenDeath;
+
+/// Slowest ball speed relative to the rules, a ball stopped by the curve
+/// would have no direction left to bounce in
+const MIN_SPEED: f32 = 0.1;
+
+/// Pace of the curve at `at`, interpolated between its points sorted by
+/// `PacingRules::sort_curve`
+fn sample(rules: &PacingRules, at: f32) -> PacingPoint {
+    let default = PacingPoint {
+        at,
+        extra_balls: 0,
+        spawn_rate: 1.0,
+        speed: 1.0,
+    };
+
+    let next = rules.curve.iter().position(|point| point.at > at);
+    let point = match next {
+        Some(0) => rules.curve.first().cloned().unwrap_or(default),
+        Some(idx) => {
+            let (from, to) = (&rules.curve[idx - 1], &rules.curve[idx]);
+            let t = (at - from.at) / (to.at - from.at);
+            let lerp = |a: f32, b: f32| a + (b - a) * t;
+            PacingPoint {
+                at,
+                extra_balls: lerp(from.extra_balls as f32, to.extra_balls as f32) as u32,
+                spawn_rate: lerp(from.spawn_rate, to.spawn_rate),
+                speed: lerp(from.speed, to.speed),
+            }
+        }
+        None => rules.curve.last().cloned().unwrap_or(default),
+    };
+
+    // A negative rate cannot scale the tick of the spawn timer
+    PacingPoint {
+        spawn_rate: point.spawn_rate.max(0.0),
+        speed: point.speed.max(MIN_SPEED),
+        ..point
+    }
+}
+
+fn pacing_reset_system(mut pacing: ResMut<Pacing>) {
+    *pacing = Pacing::default();
+}
+
+fn pacing_system(
+    mut pacing: ResMut<Pacing>,
+    mut sudden_death_events: EventWriter<SuddenDeath>,
+    score: Res<Score>,
+    rules: Res<GameRules>,
+) {
+    pacing.elapsed += TIME_STEP;
+
+    let driver = match rules.pacing.driver {
+        PacingDriver::RoundTime => pacing.elapsed,
+        PacingDriver::GoalsConceded => score.total_goals() as f32,
+    };
+
+    let point = sample(&rules.pacing, driver);
+    pacing.extra_balls = point.extra_balls;
+    pacing.spawn_rate = point.spawn_rate;
+    pacing.speed = point.speed;
+
+    let sudden_death = rules
+        .pacing
+        .sudden_death_at
+        .map_or(false, |sudden_death_at| driver >= sudden_death_at);
+    if sudden_death && !pacing.sudden_death {
+        pacing.sudden_death = true;
+        sudden_death_events.send(SuddenDeath);
+        info!("Sudden death");
+    }
+}
+
+/// Leave every player a single life once sudden death starts
+fn sudden_death_system(
+    mut sudden_death_events: EventReader<SuddenDeath>,
+    mut lives_query: Query<&mut Lives>,
+) {
+    if sudden_death_events.iter().count() > 0 {
+        for mut lives in lives_query.iter_mut() {
+            lives.0 = lives.0.min(1);
+        }
+    }
+}
+
+fn pacing_ball_speed_system(
+    mut ball_query: Query<&mut BallState, With<Ball>>,
+    pacing: Res<Pacing>,
+) {
+    for mut ball_state in ball_query.iter_mut() {
+        ball_state.speedup = pacing.speed - 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(at: f32, extra_balls: u32, spawn_rate: f32, speed: f32) -> PacingPoint {
+        PacingPoint {
+            at,
+            extra_balls,
+            spawn_rate,
+            speed,
+        }
+    }
+
+    fn rules(curve: Vec<PacingPoint>) -> PacingRules {
+        PacingRules {
+            driver: PacingDriver::RoundTime,
+            curve,
+            sudden_death_at: None,
+        }
+    }
+
+    fn curve() -> Vec<PacingPoint> {
+        vec![point(10.0, 0, 1.0, 1.0), point(20.0, 2, 2.0, 1.5)]
+    }
+
+    #[test]
+    fn sample_holds_the_first_point_before_it() {
+        let point = sample(&rules(curve()), 5.0);
+        assert_eq!(point.extra_balls, 0);
+        assert_eq!(point.spawn_rate, 1.0);
+        assert_eq!(point.speed, 1.0);
+    }
+
+    #[test]
+    fn sample_interpolates_between_two_points() {
+        let point = sample(&rules(curve()), 15.0);
+        assert_eq!(point.extra_balls, 1);
+        assert!((point.spawn_rate - 1.5).abs() < 1e-6);
+        assert!((point.speed - 1.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sample_holds_the_last_point_after_it() {
+        let point = sample(&rules(curve()), 100.0);
+        assert_eq!(point.extra_balls, 2);
+        assert_eq!(point.spawn_rate, 2.0);
+        assert_eq!(point.speed, 1.5);
+    }
+
+    #[test]
+    fn sample_keeps_the_pace_positive() {
+        let curve = vec![point(0.0, 0, -1.0, 0.0), point(10.0, 0, 1.0, -2.0)];
+        for at in [0.0, 5.0, 10.0] {
+            let point = sample(&rules(curve.clone()), at);
+            assert!(point.spawn_rate >= 0.0);
+            assert!(point.speed >= MIN_SPEED);
+        }
+    }
+
+    #[test]
+    fn sample_reads_an_unsorted_curve_once_sorted() {
+        let mut unsorted = rules(curve().into_iter().rev().collect());
+        unsorted.sort_curve();
+        let sorted = rules(curve());
+        for at in [5.0, 10.0, 15.0, 20.0, 100.0] {
+            let a = sample(&unsorted, at);
+            let b = sample(&sorted, at);
+            assert_eq!(a.extra_balls, b.extra_balls);
+            assert_eq!(a.spawn_rate, b.spawn_rate);
+            assert_eq!(a.speed, b.speed);
+        }
+    }
+}
@@ -1,54 +1,267 @@
+use std::time::Duration;
+
 use crate::{
-    ball::{ball_update_speed, Ball, BallState, BALL_RADIUS},
-    corner::CORNER_RADIUS,
-    util::{clamp, Rectangle},
-    ArenaInfo, TIME_STEP,
+    ai::{AiController, Difficulty},
+    arena::ArenaConfig,
+    ball::{ball_update_speed, Ball, BallKind, BallState},
+    controls::Controls,
+    game::{GameRng, GameState, GameplayStep, MatchSetup, RoundEntity, RoundSetup},
+    powerup::{FreeBallFilter, PowerUpEffects},
+    rules::{EnergyRules, GameRules},
+    score::GoalScored,
+    tick::TickApp,
+    util::{clamp, Rectangle, Side},
+    ArenaInfo, GoalSides, TIME_STEP,
 };
 use bevy::{prelude::*, sprite::collide_aabb::collide};
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
-use leafwing_input_manager::prelude::*;
+use leafwing_input_manager::{prelude::*, user_input::InputKind};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system_to_stage(StartupStage::PostStartup, player_spawn_system)
-            .add_plugin(InputManagerPlugin::<PlayerAction>::default())
-            .add_system(player_keyboard_event_system)
-            .add_system(player_fire_energy)
-            .add_system(player_update_energy)
-            .add_system(player_energy_hit_ball);
+        app.add_tick_actions::<PlayerAction>()
+            .init_resource::<Controls>()
+            .add_tick_event::<PlayerEliminated>()
+            .add_tick_event::<RoundOver>()
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Countdown).with_system(
+                    player_spawn_system
+                        .label(RoundSetup::Players)
+                        .after(RoundSetup::GoalZones),
+                ),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        player_keyboard_event_system
+                            .label(GameplayStep::Player)
+                            .after(GameplayStep::Ai),
+                    )
+                    .with_system(
+                        player_energy_meter_system
+                            .label(GameplayStep::Player)
+                            .after(player_keyboard_event_system),
+                    )
+                    .with_system(
+                        player_fire_energy
+                            .label(GameplayStep::Player)
+                            .after(player_energy_meter_system),
+                    )
+                    .with_system(
+                        player_charge_ring_system
+                            .label(GameplayStep::Player)
+                            .after(player_fire_energy),
+                    )
+                    .with_system(
+                        player_update_energy
+                            .label(GameplayStep::Player)
+                            .after(player_charge_ring_system),
+                    )
+                    .with_system(
+                        player_energy_hit_ball
+                            .label(GameplayStep::Player)
+                            .after(player_update_energy),
+                    )
+                    .with_system(
+                        player_lives_system
+                            .label(GameplayStep::Elimination)
+                            .after(GameplayStep::Score),
+                    ),
+            );
     }
 }
 
-pub const PLAYER_BASE_SPEED: f32 = 350.0;
-pub const PLAYER_ACCELERATE_SPEED: f32 = 550.0;
-pub const PLAYER_RADIUS: f32 = 70.0;
-pub const PLAYER_ENERGY_RADIUS: f32 = 80.0; // on top of PLAYER_RADIUS
 pub const GAMEPAD_AXIS_THRESHOLD: f32 = 0.3;
 
+/// Who drives a player
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Controller {
+    Human(InputDevice),
+    Cpu(Difficulty),
+    /// Inputs read from a recorded match
+    Replay,
+}
+
+/// Device a human player plays with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputDevice {
+    Keyboard(KeyboardLayout),
+    Gamepad(usize),
+}
+
+impl InputDevice {
+    /// Bindings of `input_map` this device can press, from its gamepad only
+    pub fn input_map(&self, input_map: &InputMap<PlayerAction>) -> InputMap<PlayerAction> {
+        let mut device_map = InputMap::default();
+        for (inputs, action) in input_map.iter() {
+            for input in inputs.iter().filter(|input| self.handles(input)) {
+                device_map.insert(input.clone(), action);
+            }
+        }
+
+        if let InputDevice::Gamepad(id) = self {
+            device_map.set_gamepad(Gamepad { id: *id });
+        }
+        device_map
+    }
+
+    fn handles(&self, input: &UserInput) -> bool {
+        let keyboard = matches!(input, UserInput::Single(InputKind::Keyboard(_)));
+        match self {
+            InputDevice::Keyboard(_) => keyboard,
+            InputDevice::Gamepad(_) => !keyboard,
+        }
+    }
+
+    /// Layout whose keys are bound by default, whichever for a gamepad
+    pub fn layout(&self) -> KeyboardLayout {
+        match self {
+            InputDevice::Keyboard(layout) => *layout,
+            InputDevice::Gamepad(_) => KeyboardLayout::Arrows,
+        }
+    }
+}
+
+/// Part of the keyboard a human player plays with, so that several players can
+/// share it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+pub enum KeyboardLayout {
+    #[default]
+    Arrows,
+    Wasd,
+    Ijkl,
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 3] = [
+        KeyboardLayout::Arrows,
+        KeyboardLayout::Wasd,
+        KeyboardLayout::Ijkl,
+    ];
+
+    /// Keys moving left and right, accelerating and firing energy
+    fn keys(&self) -> [KeyCode; 4] {
+        match self {
+            KeyboardLayout::Arrows => [
+                KeyCode::Left,
+                KeyCode::Right,
+                KeyCode::LControl,
+                KeyCode::Space,
+            ],
+            KeyboardLayout::Wasd => [KeyCode::A, KeyCode::D, KeyCode::S, KeyCode::W],
+            KeyboardLayout::Ijkl => [KeyCode::J, KeyCode::L, KeyCode::K, KeyCode::I],
+        }
+    }
+
+    /// Key joining the match in the menu, the one firing energy
+    pub fn join_key(&self) -> KeyCode {
+        self.keys()[3]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlayerSetup {
+    pub side: Side,
+    pub controller: Controller,
+}
+
+#[derive(Component)]
+pub struct Player {
+    pub side: Side,
+}
+
+/// Speed of a player along its side, positive towards its right
+#[derive(Component, Default)]
+pub struct PlayerVelocity(pub f32);
+
+/// Current radius of a player, grown by the wide paddle power-up
+#[derive(Component)]
+pub struct PlayerRadius(pub f32);
+
+/// Goals a player can still concede before being eliminated
 #[derive(Component)]
-struct Player;
+pub struct Lives(pub u32);
 
+/// Energy a player spends on pulses, regenerating over time
 #[derive(Component)]
-struct PlayerEnergy;
+pub struct EnergyMeter {
+    pub value: f32,
+    /// Runs from the release of each pulse, no pulse can be charged until it
+    /// finishes
+    pub cooldown: Timer,
+    /// Charge of the pulse being held, from 0 to 1
+    pub charge: Option<f32>,
+}
+
+impl EnergyMeter {
+    fn new(rules: &EnergyRules) -> Self {
+        let mut cooldown = Timer::from_seconds(rules.cooldown, false);
+        // Ready for a pulse as soon as the round starts
+        cooldown.tick(cooldown.duration());
 
-#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug)]
-enum PlayerAction {
+        Self {
+            value: rules.capacity,
+            cooldown,
+            charge: None,
+        }
+    }
+}
+
+/// Energy ring expanding around a player, energizing the balls it reaches
+#[derive(Component)]
+struct PlayerEnergy {
+    /// Radius of the ring before it expands
+    radius: f32,
+    /// Scale of the ring when it vanishes
+    max_scale: f32,
+    /// Extra speed factor given to the balls
+    boost: f32,
+}
+
+/// Ring showing how far the pulse being charged will reach
+#[derive(Component)]
+struct PlayerCharge;
+
+/// Sent when a player runs out of lives, its side is then closed by a barrier
+pub struct PlayerEliminated {
+    pub side: Side,
+}
+
+/// Sent when at most one player is left in the arena
+pub struct RoundOver {
+    pub winner: Option<Side>,
+}
+
+#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Serialize, Deserialize)]
+pub enum PlayerAction {
     Move,
     MoveLeft,
     MoveRight,
     Accelerate,
     Energy,
+    Pause,
 }
 
 impl PlayerAction {
-    fn default_key_map() -> InputMap<PlayerAction> {
+    /// Pause alone, for the players who don't control a paddle
+    pub fn pause_key_map() -> InputMap<PlayerAction> {
         let mut input_map = InputMap::default();
+        input_map.insert(KeyCode::Escape, PlayerAction::Pause);
+        input_map.insert(GamepadButtonType::Start, PlayerAction::Pause);
+        input_map
+    }
+
+    pub fn default_key_map(layout: KeyboardLayout) -> InputMap<PlayerAction> {
+        let mut input_map = Self::pause_key_map();
+        let [left, right, accelerate, energy] = layout.keys();
 
         // Move left
-        input_map.insert(KeyCode::Left, PlayerAction::MoveLeft);
+        input_map.insert(left, PlayerAction::MoveLeft);
         input_map.insert(GamepadButtonType::DPadLeft, PlayerAction::MoveLeft);
         input_map.insert(
             SingleAxis::symmetric(GamepadAxisType::LeftStickX, DualAxis::DEFAULT_DEADZONE),
@@ -56,75 +269,134 @@ impl PlayerAction {
         );
 
         // Move right
-        input_map.insert(KeyCode::Right, PlayerAction::MoveRight);
+        input_map.insert(right, PlayerAction::MoveRight);
         input_map.insert(GamepadButtonType::DPadRight, PlayerAction::MoveRight);
 
-        //input_map.insert(GamepadAxisType::LeftStickX, PlayerAction::Move);
-
         // Accelerate
-        input_map.insert(KeyCode::LControl, PlayerAction::Accelerate);
+        input_map.insert(accelerate, PlayerAction::Accelerate);
         input_map.insert(GamepadButtonType::RightTrigger, PlayerAction::Accelerate);
 
         // Energy
-        input_map.insert(KeyCode::Space, PlayerAction::Energy);
+        input_map.insert(energy, PlayerAction::Energy);
         input_map.insert(GamepadButtonType::West, PlayerAction::Energy);
 
         input_map
     }
 }
 
-fn player_spawn_system(mut commands: Commands, arena_info: Res<ArenaInfo>) {
+pub fn player_color(side: Side) -> Color {
+    match side {
+        Side::Bottom => Color::CYAN,
+        Side::Right => Color::ORANGE,
+        Side::Top => Color::LIME_GREEN,
+        Side::Left => Color::PINK,
+    }
+}
+
+fn player_spawn_system(
+    mut commands: Commands,
+    arena_info: Res<ArenaInfo>,
+    goal_sides: Res<GoalSides>,
+    match_setup: Res<MatchSetup>,
+    mut rng: ResMut<GameRng>,
+    rules: Res<GameRules>,
+    controls: Res<Controls>,
+) {
+    let energy_rules = &rules.energy;
+    let rules = &rules.player;
     let shape = shapes::Circle {
-        radius: PLAYER_RADIUS,
+        radius: rules.radius,
         ..Default::default()
     };
 
-    let left_bound = arena_info.0.left() + PLAYER_RADIUS + CORNER_RADIUS;
-    let right_bound = arena_info.0.right() - PLAYER_RADIUS - CORNER_RADIUS;
+    for setup in match_setup
+        .players
+        .iter()
+        .filter(|setup| goal_sides.0.contains(&setup.side))
+    {
+        let side = setup.side;
+        // Drawn for every player whatever its controller, so that a replay
+        // consumes the game randomness exactly like the recorded match
+        let ai_seed = rng.0.gen();
 
-    commands
-        .spawn_bundle(GeometryBuilder::build_as(
+        let mut player_commands = commands.spawn_bundle(GeometryBuilder::build_as(
             &shape,
-            DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(Color::CYAN)),
+            DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode::color(player_color(
+                side,
+            ))),
             Transform {
-                translation: arena_info.0.bottom_middle(),
+                translation: arena_info.0.side_middle(side),
                 ..Default::default()
             },
-        ))
-        .insert(Player)
-        .insert(RigidBody::KinematicPositionBased)
-        .insert(LockedAxes::ROTATION_LOCKED)
-        .insert(Restitution {
-            coefficient: 1.0,
-            combine_rule: CoefficientCombineRule::Min,
-        })
-        .insert(Friction {
-            coefficient: 0.65,
-            combine_rule: CoefficientCombineRule::Max,
-        })
-        .insert(Collider::ball(PLAYER_RADIUS))
-        .insert_bundle(InputManagerBundle::<PlayerAction> {
-            // Stores "which actions are currently pressed"
-            action_state: ActionState::default(),
-            // Describes how to convert from player inputs into those actions
-            input_map: PlayerAction::default_key_map(),
-        });
+        ));
+
+        player_commands
+            .insert(Player { side })
+            .insert(RoundEntity)
+            .insert(Lives(rules.lives))
+            .insert(PlayerVelocity::default())
+            .insert(PlayerRadius(rules.radius))
+            .insert(PowerUpEffects::default())
+            .insert(EnergyMeter::new(energy_rules))
+            .insert(RigidBody::KinematicPositionBased)
+            .insert(LockedAxes::ROTATION_LOCKED)
+            .insert(Restitution {
+                coefficient: 1.0,
+                combine_rule: CoefficientCombineRule::Min,
+            })
+            .insert(Friction {
+                coefficient: 0.65,
+                combine_rule: CoefficientCombineRule::Max,
+            })
+            .insert(Collider::ball(rules.radius));
+
+        match setup.controller {
+            Controller::Human(device) => {
+                // Several players share the keyboard, and each gamepad has its player
+                let input_map = device.input_map(&controls.input_map(side, device.layout()));
+
+                player_commands.insert_bundle(InputManagerBundle::<PlayerAction> {
+                    // Stores "which actions are currently pressed"
+                    action_state: ActionState::default(),
+                    // Describes how to convert from player inputs into those actions
+                    input_map,
+                });
+            }
+            Controller::Cpu(difficulty) => {
+                // No input map: the AI presses the actions itself
+                player_commands
+                    .insert(ActionState::<PlayerAction>::default())
+                    .insert(AiController::new(difficulty, ai_seed));
+            }
+            Controller::Replay => {
+                // No input map: the replay presses the recorded actions
+                player_commands.insert(ActionState::<PlayerAction>::default());
+            }
+        }
+    }
 }
 
 fn player_keyboard_event_system(
-    mut player_query: Query<(&mut Transform, &ActionState<PlayerAction>), With<Player>>,
+    mut player_query: Query<(
+        &Player,
+        &PlayerRadius,
+        &mut Transform,
+        &mut PlayerVelocity,
+        &ActionState<PlayerAction>,
+    )>,
     area_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+    rules: Res<GameRules>,
 ) {
-    let left_bound = area_info.0.left() + CORNER_RADIUS + PLAYER_RADIUS;
-    let right_bound = area_info.0.right() - CORNER_RADIUS - PLAYER_RADIUS;
-
-    if let Ok((mut rb_trans, action_state)) = player_query.get_single_mut() {
-        let x_axis = if (action_state.pressed(PlayerAction::MoveLeft)) {
+    let rules = &rules.player;
+    for (player, radius, mut rb_trans, mut velocity, action_state) in player_query.iter_mut() {
+        // Left and right are seen from the player, facing the arena
+        let x_axis = if action_state.pressed(PlayerAction::MoveLeft) {
             -1.0
-        } else if (action_state.pressed(PlayerAction::MoveRight)) {
+        } else if action_state.pressed(PlayerAction::MoveRight) {
             1.0
-        } else if (action_state.pressed(PlayerAction::Move)) {
-            match (action_state.clamped_value(PlayerAction::Move)) {
+        } else if action_state.pressed(PlayerAction::Move) {
+            match action_state.clamped_value(PlayerAction::Move) {
                 num if num > GAMEPAD_AXIS_THRESHOLD => 1.0,
                 num if num < -GAMEPAD_AXIS_THRESHOLD => -1.0,
                 _ => 0.0,
@@ -133,75 +405,219 @@ fn player_keyboard_event_system(
             0.0
         };
 
-        let player_speed = if (action_state.pressed(PlayerAction::Accelerate)) {
-            PLAYER_ACCELERATE_SPEED
+        let player_speed = if action_state.pressed(PlayerAction::Accelerate) {
+            rules.accelerate_speed
         } else {
-            PLAYER_BASE_SPEED
+            rules.speed
         };
 
-        rb_trans.translation.x += x_axis as f32 * player_speed * TIME_STEP;
-        rb_trans.translation.x = clamp(rb_trans.translation.x, left_bound, right_bound);
+        // Move along the side, between the two corners
+        let side_middle = area_info.0.side_middle(player.side);
+        let tangent = player.side.tangent();
+        let bound =
+            area_info.0.side_length(player.side) / 2.0 - arena_config.corner_radius - radius.0;
+
+        let previous_offset = (rb_trans.translation - side_middle).truncate().dot(tangent);
+        let offset = clamp(
+            previous_offset + x_axis * player_speed * TIME_STEP,
+            -bound,
+            bound,
+        );
+
+        rb_trans.translation = side_middle + (tangent * offset).extend(0.0);
+        velocity.0 = (offset - previous_offset) / TIME_STEP;
     }
 }
 
+fn player_energy_meter_system(mut query: Query<&mut EnergyMeter>, rules: Res<GameRules>) {
+    for mut meter in query.iter_mut() {
+        meter.value = (meter.value + rules.energy.regen * TIME_STEP).min(rules.energy.capacity);
+        meter.cooldown.tick(Duration::from_secs_f32(TIME_STEP));
+    }
+}
+
+/// Charge a pulse while the energy action is held, and fire it on release
 fn player_fire_energy(
     mut commands: Commands,
-    player_query: Query<(Entity, &Transform, &ActionState<PlayerAction>), With<Player>>,
+    mut player_query: Query<(
+        Entity,
+        &Player,
+        &PlayerRadius,
+        &ActionState<PlayerAction>,
+        &mut EnergyMeter,
+    )>,
+    charge_query: Query<(Entity, &Parent), With<PlayerCharge>>,
+    rules: Res<GameRules>,
 ) {
-    if let Ok((player_id, player_tf, action_state)) = player_query.get_single() {
-        if action_state.just_pressed(PlayerAction::Energy) {
-            // Spawn the energy effect
-            let shape = shapes::Circle {
-                radius: PLAYER_RADIUS,
-                ..Default::default()
-            };
+    let energy_rules = &rules.energy;
+
+    for (player_id, player, radius, action_state, mut meter) in player_query.iter_mut() {
+        let shape = shapes::Circle {
+            radius: radius.0,
+            ..Default::default()
+        };
+
+        if action_state.just_pressed(PlayerAction::Energy)
+            && meter.charge.is_none()
+            && meter.cooldown.finished()
+            && meter.value >= energy_rules.pulse_cost
+        {
+            meter.charge = Some(0.0);
 
-            let energy = commands
+            let charge_ring = commands
                 .spawn_bundle(GeometryBuilder::build_as(
                     &shape,
-                    DrawMode::Stroke(StrokeMode::new(Color::WHITE, 5.0)),
+                    DrawMode::Stroke(StrokeMode::new(player_color(player.side), 2.0)),
                     Transform::default(),
                 ))
-                .insert(PlayerEnergy)
+                .insert(PlayerCharge)
                 .id();
+            commands.entity(player_id).push_children(&[charge_ring]);
+        }
 
-            commands.entity(player_id).push_children(&[energy]);
+        let charge = match meter.charge {
+            Some(charge) => charge,
+            None => continue,
+        };
+
+        if action_state.pressed(PlayerAction::Energy) {
+            meter.charge = Some((charge + TIME_STEP / energy_rules.charge_time).min(1.0));
+            continue;
+        }
+
+        // Released: spend the energy and spawn the energy effect
+        meter.charge = None;
+        meter.value -= energy_rules.pulse_cost;
+        meter.cooldown.reset();
+
+        for (charge_id, parent) in charge_query.iter() {
+            if parent.get() == player_id {
+                commands.entity(charge_id).despawn();
+            }
+        }
+
+        let radius_factor = 1.0 + (energy_rules.charged_radius_factor - 1.0) * charge;
+        let energy = commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shape,
+                DrawMode::Stroke(StrokeMode::new(Color::WHITE, 3.0 + 5.0 * charge)),
+                Transform::default(),
+            ))
+            .insert(PlayerEnergy {
+                radius: radius.0,
+                max_scale: 1.0 + rules.player.energy_radius * radius_factor / radius.0,
+                boost: 1.0 + (energy_rules.charged_speed_factor - 1.0) * charge,
+            })
+            .id();
+
+        commands.entity(player_id).push_children(&[energy]);
+    }
+}
+
+/// Grow the charge ring towards the reach of the pulse being charged
+fn player_charge_ring_system(
+    meter_query: Query<(&EnergyMeter, &PlayerRadius)>,
+    mut charge_query: Query<(&Parent, &mut Transform), With<PlayerCharge>>,
+    rules: Res<GameRules>,
+) {
+    for (parent, mut transform) in charge_query.iter_mut() {
+        if let Ok((
+            EnergyMeter {
+                charge: Some(charge),
+                ..
+            },
+            radius,
+        )) = meter_query.get(parent.get())
+        {
+            let radius_factor = 1.0 + (rules.energy.charged_radius_factor - 1.0) * charge;
+            let scale = 1.0 + rules.player.energy_radius * radius_factor / radius.0;
+            transform.scale = Vec3::new(scale, scale, 1.0);
         }
     }
 }
 
 fn player_update_energy(
     mut commands: Commands,
-    mut query: Query<(Entity, &mut Transform), With<PlayerEnergy>>,
+    mut query: Query<(Entity, &PlayerEnergy, &mut Transform)>,
 ) {
-    let scale_threshold = 1.0 + PLAYER_ENERGY_RADIUS / PLAYER_RADIUS;
-    for (id, mut transform) in query.iter_mut() {
-        transform.scale += scale_threshold * 2.0 * TIME_STEP;
+    for (id, energy, mut transform) in query.iter_mut() {
+        transform.scale += energy.max_scale * 2.0 * TIME_STEP;
 
-        if transform.scale.x > scale_threshold {
+        if transform.scale.x > energy.max_scale {
             commands.entity(id).despawn();
         }
     }
 }
 
 fn player_energy_hit_ball(
-    player_query: Query<(&Transform), With<Player>>,
-    player_energy_query: Query<(&Transform), With<PlayerEnergy>>,
-    mut balls_query: Query<(&mut BallState, &Transform, &mut Velocity), With<Ball>>,
+    player_query: Query<(&Player, &Transform)>,
+    player_energy_query: Query<(&Parent, &PlayerEnergy, &Transform)>,
+    mut balls_query: Query<(&mut BallState, &BallKind, &Transform, &mut Velocity), FreeBallFilter>,
+    rules: Res<GameRules>,
 ) {
-    if let Ok(player_transform) = player_query.get_single() {
-        for pe_transf in player_energy_query.iter() {
-            let pe_radius = PLAYER_RADIUS * pe_transf.scale.x;
-            let threashold_dist = pe_radius + BALL_RADIUS;
-
-            // Move the balls in the range
-            for (mut ball_state, ball_tf, mut velocity) in balls_query.iter_mut() {
-                let vect = ball_tf.translation.truncate() - player_transform.translation.truncate();
-                if vect.length() < threashold_dist {
-                    ball_state.energize();
-                    ball_update_speed(vect, &ball_state, &mut velocity);
-                }
+    for (parent, energy, pe_transf) in player_energy_query.iter() {
+        let (player, player_transform) = match player_query.get(parent.get()) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+
+        let pe_radius = energy.radius * pe_transf.scale.x;
+
+        // Move the balls in the range
+        for (mut ball_state, kind, ball_tf, mut velocity) in balls_query.iter_mut() {
+            let threashold_dist = pe_radius + kind.radius(&rules.ball);
+            let vect = ball_tf.translation.truncate() - player_transform.translation.truncate();
+            if vect.length() < threashold_dist {
+                ball_state.energize(parent.get(), player.side, energy.boost, &rules.ball);
+                // Heavy balls push through the pulse, only taking its energy
+                let direction = if *kind == BallKind::Heavy {
+                    velocity.linvel
+                } else {
+                    vect
+                };
+                ball_update_speed(direction, &ball_state, kind, &rules.ball, &mut velocity);
             }
         }
     }
 }
+
+fn player_lives_system(
+    mut commands: Commands,
+    mut goal_events: EventReader<GoalScored>,
+    mut eliminated_events: EventWriter<PlayerEliminated>,
+    mut round_events: EventWriter<RoundOver>,
+    mut goal_sides: ResMut<GoalSides>,
+    mut player_query: Query<(Entity, &Player, &mut Lives)>,
+) {
+    let mut elimination = false;
+
+    for goal in goal_events.iter() {
+        for (player_id, player, mut lives) in player_query.iter_mut() {
+            if player.side != goal.side || lives.0 == 0 {
+                continue;
+            }
+
+            lives.0 -= 1;
+            if lives.0 == 0 {
+                commands.entity(player_id).despawn_recursive();
+                goal_sides.0.retain(|side| *side != player.side);
+                eliminated_events.send(PlayerEliminated { side: player.side });
+                elimination = true;
+            }
+        }
+    }
+
+    if elimination {
+        let survivors: Vec<Side> = player_query
+            .iter()
+            .filter(|(_, _, lives)| lives.0 > 0)
+            .map(|(_, player, _)| player.side)
+            .collect();
+
+        if survivors.len() <= 1 {
+            round_events.send(RoundOver {
+                winner: survivors.first().copied(),
+            });
+        }
+    }
+}
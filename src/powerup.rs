@@ -0,0 +1,610 @@
+use std::time::Duration;
+
+use crate::{
+    arena::ArenaConfig,
+    ball::{deflect, split_ball, Ball, BallHitPlayer, BallHitPowerUp, BallKind, BallState},
+    barrier::{spawn_barrier, Barrier},
+    game::{GameRng, GameState, GameplayStep, RoundEntity},
+    player::{player_color, Player, PlayerAction, PlayerEliminated, PlayerRadius, PlayerVelocity},
+    rules::GameRules,
+    tick::TickApp,
+    util::{clamp, Rectangle, Side},
+    ArenaInfo, TIME_STEP,
+};
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::prelude::*;
+use rand::Rng;
+
+pub struct PowerUpPlugin;
+
+impl Plugin for PowerUpPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_event::<PowerUpPicked>()
+            .insert_resource(PowerUpSpawnTimer(Timer::default()))
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Countdown).with_system(power_up_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        power_up_spawn_system
+                            .label(GameplayStep::PowerUp)
+                            .after(GameplayStep::Ball),
+                    )
+                    .with_system(
+                        power_up_pickup_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_spawn_system),
+                    )
+                    .with_system(
+                        power_up_grant_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_pickup_system),
+                    )
+                    .with_system(
+                        power_up_expiry_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_grant_system),
+                    )
+                    .with_system(
+                        power_up_ball_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_expiry_system),
+                    )
+                    .with_system(
+                        power_up_catch_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_ball_system),
+                    )
+                    .with_system(
+                        power_up_hold_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_catch_system),
+                    )
+                    .with_system(
+                        power_up_visual_system
+                            .label(GameplayStep::PowerUp)
+                            .after(power_up_hold_system),
+                    )
+                    .with_system(
+                        shield_elimination_system
+                            .label(GameplayStep::Arena)
+                            .after(GameplayStep::Elimination),
+                    ),
+            );
+    }
+}
+
+/// Turns per second of a pickup, just for show
+const PICKUP_SPIN: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PowerUpKind {
+    /// The player grows, covering more of its side
+    WidePaddle,
+    /// The ball hitting the pickup splits into several balls
+    MultiBall,
+    /// The balls last touched by an opponent move slower
+    SlowMotion,
+    /// The balls coming towards the player bend towards it, and the player
+    /// catches them
+    Magnet,
+    /// A wall closes the goal of the player
+    Shield,
+}
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 5] = [
+        PowerUpKind::WidePaddle,
+        PowerUpKind::MultiBall,
+        PowerUpKind::SlowMotion,
+        PowerUpKind::Magnet,
+        PowerUpKind::Shield,
+    ];
+
+    pub fn color(&self) -> Color {
+        match self {
+            PowerUpKind::WidePaddle => Color::GOLD,
+            PowerUpKind::MultiBall => Color::WHITE,
+            PowerUpKind::SlowMotion => Color::rgb(0.3, 0.5, 1.0),
+            PowerUpKind::Magnet => Color::PURPLE,
+            PowerUpKind::Shield => Color::TEAL,
+        }
+    }
+}
+
+/// Pickup waiting in the arena for a ball to hit it
+#[derive(Component)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+}
+
+/// Sent when a ball hits a pickup, `player` being the last one who touched it
+pub struct PowerUpPicked {
+    pub kind: PowerUpKind,
+    pub player: Entity,
+    pub ball: Entity,
+}
+
+/// Lasting effects of a player, along with the time they have left
+#[derive(Component, Default)]
+pub struct PowerUpEffects {
+    effects: Vec<(PowerUpKind, Timer)>,
+}
+
+impl PowerUpEffects {
+    pub fn has(&self, kind: PowerUpKind) -> bool {
+        self.effects.iter().any(|(active, _)| *active == kind)
+    }
+
+    /// Effect picked last among the active ones
+    pub fn latest(&self) -> Option<PowerUpKind> {
+        self.effects.last().map(|(kind, _)| *kind)
+    }
+
+    /// Start `kind` for `duration` seconds. Effects of the same kind do not
+    /// stack, picking an active one again only restarts it. Returns whether
+    /// the effect was not active yet.
+    fn grant(&mut self, kind: PowerUpKind, duration: f32) -> bool {
+        let started = !self.has(kind);
+        self.effects.retain(|(active, _)| *active != kind);
+        self.effects
+            .push((kind, Timer::from_seconds(duration, false)));
+        started
+    }
+
+    /// Run the effects for `delta`, returning the ones that ended
+    fn tick(&mut self, delta: Duration) -> Vec<PowerUpKind> {
+        let mut expired = Vec::new();
+        self.effects.retain_mut(|(kind, timer)| {
+            if timer.tick(delta).finished() {
+                expired.push(*kind);
+                false
+            } else {
+                true
+            }
+        });
+        expired
+    }
+}
+
+/// Wall closing the goal of a player under the shield effect
+#[derive(Component)]
+struct Shield {
+    owner: Entity,
+}
+
+/// Ball held by a player under the magnet effect, until the player presses
+/// energy or `PowerUpRules::catch_time` runs out
+#[derive(Component)]
+pub struct Caught {
+    player: Entity,
+    side: Side,
+    /// Where the ball is held, from -1 on the left edge of the player to 1 on
+    /// its right edge, the ball being thrown back that way
+    offset: f32,
+    speed: f32,
+    timer: Timer,
+}
+
+/// Balls not held by a player
+pub type FreeBallFilter = (With<Ball>, Without<Caught>);
+
+/// Player holding a caught ball, and what may make it let go
+type Holder<'a> = (
+    &'a PlayerRadius,
+    &'a PlayerVelocity,
+    &'a PowerUpEffects,
+    &'a ActionState<PlayerAction>,
+    &'a Transform,
+);
+
+/// Gap between a held ball and the player, so that they don't collide
+const CATCH_GAP: f32 = 2.0;
+
+struct PowerUpSpawnTimer(Timer);
+
+fn power_up_reset_system(mut spawn_timer: ResMut<PowerUpSpawnTimer>, rules: Res<GameRules>) {
+    spawn_timer.0 = Timer::from_seconds(rules.power_ups.spawn_interval, true);
+}
+
+fn power_up_spawn_system(
+    mut commands: Commands,
+    mut spawn_timer: ResMut<PowerUpSpawnTimer>,
+    mut rng: ResMut<GameRng>,
+    power_up_query: Query<(), With<PowerUp>>,
+    arena_info: Res<ArenaInfo>,
+    rules: Res<GameRules>,
+) {
+    if !spawn_timer
+        .0
+        .tick(Duration::from_secs_f32(TIME_STEP))
+        .just_finished()
+    {
+        return;
+    }
+
+    let power_up_rules = &rules.power_ups;
+    if power_up_query.iter().count() >= power_up_rules.max_count as usize {
+        return;
+    }
+
+    // Out of reach of the players and their energy, only a ball can pick it
+    let inset = rules.player.radius + rules.player.energy_radius;
+    let (left, right) = (arena_info.0.left() + inset, arena_info.0.right() - inset);
+    let (bottom, top) = (arena_info.0.bottom() + inset, arena_info.0.top() - inset);
+    let position = Vec2 {
+        x: rng.0.gen_range(left..=right.max(left)),
+        y: rng.0.gen_range(bottom..=top.max(bottom)),
+    };
+    let kind = PowerUpKind::ALL[rng.0.gen_range(0..PowerUpKind::ALL.len())];
+
+    let shape = shapes::RegularPolygon {
+        sides: 6,
+        feature: RegularPolygonFeature::Radius(power_up_rules.radius),
+        ..Default::default()
+    };
+
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &shape,
+            DrawMode::Outlined {
+                fill_mode: bevy_prototype_lyon::prelude::FillMode::color(kind.color()),
+                outline_mode: StrokeMode::new(Color::WHITE, 2.0),
+            },
+            Transform {
+                translation: position.extend(5.0),
+                ..Default::default()
+            },
+        ))
+        .insert(PowerUp { kind })
+        .insert(RoundEntity)
+        .insert(Collider::ball(power_up_rules.radius))
+        .insert(Sensor);
+}
+
+/// Give the pickups hit by a ball to the last player who touched that ball.
+/// Balls no player touched yet go through the pickups.
+fn power_up_pickup_system(
+    mut commands: Commands,
+    mut power_up_hits: EventReader<BallHitPowerUp>,
+    mut picked_events: EventWriter<PowerUpPicked>,
+    power_up_query: Query<&PowerUp>,
+    ball_query: Query<&BallState, With<Ball>>,
+    player_query: Query<(), With<Player>>,
+) {
+    let mut picked = Vec::new();
+
+    for hit in power_up_hits.iter() {
+        let power_up = match power_up_query.get(hit.power_up) {
+            Ok(power_up) => power_up,
+            Err(_) => continue,
+        };
+        let player = match ball_query
+            .get(hit.ball)
+            .ok()
+            .and_then(|ball_state| ball_state.last_touch)
+            .filter(|player| player_query.contains(*player))
+        {
+            Some(player) => player,
+            None => continue,
+        };
+
+        // Two balls may reach the same pickup during a tick
+        if picked.contains(&hit.power_up) {
+            continue;
+        }
+        picked.push(hit.power_up);
+
+        commands.entity(hit.power_up).despawn();
+        picked_events.send(PowerUpPicked {
+            kind: power_up.kind,
+            player,
+            ball: hit.ball,
+        });
+    }
+}
+
+/// Resize a player, its collider and its shape
+fn resize_player(
+    radius: f32,
+    player_radius: &mut PlayerRadius,
+    collider: &mut Collider,
+    path: &mut Path,
+) {
+    player_radius.0 = radius;
+    *collider = Collider::ball(radius);
+    *path = ShapePath::build_as(&shapes::Circle {
+        radius,
+        ..Default::default()
+    });
+}
+
+fn power_up_grant_system(
+    mut commands: Commands,
+    mut picked_events: EventReader<PowerUpPicked>,
+    mut player_query: Query<(
+        &Player,
+        &mut PowerUpEffects,
+        &mut PlayerRadius,
+        &mut Collider,
+        &mut Path,
+    )>,
+    ball_query: Query<(&BallState, &Transform, &Velocity), With<Ball>>,
+    arena_info: Res<ArenaInfo>,
+    arena_config: Res<ArenaConfig>,
+    rules: Res<GameRules>,
+) {
+    for picked in picked_events.iter() {
+        let (player, mut effects, mut radius, mut collider, mut path) =
+            match player_query.get_mut(picked.player) {
+                Ok(player) => player,
+                Err(_) => continue,
+            };
+        info!("{:?} picked {:?}", player.side, picked.kind);
+
+        if picked.kind == PowerUpKind::MultiBall {
+            if let Ok((ball_state, ball_tf, velocity)) = ball_query.get(picked.ball) {
+                split_ball(
+                    &mut commands,
+                    &rules.ball,
+                    ball_state,
+                    ball_tf.translation.truncate(),
+                    velocity.linvel,
+                    rules.power_ups.multi_ball_count,
+                );
+            }
+            continue;
+        }
+
+        if !effects.grant(picked.kind, rules.power_ups.duration) {
+            continue;
+        }
+
+        match picked.kind {
+            PowerUpKind::WidePaddle => resize_player(
+                rules.player.radius * rules.power_ups.wide_paddle_factor,
+                &mut radius,
+                &mut collider,
+                &mut path,
+            ),
+            PowerUpKind::Shield => {
+                let shield = spawn_barrier(&mut commands, &arena_info, &arena_config, player.side);
+                // Just behind the player, so that balls can still hit it
+                let offset = player.side.normal() * rules.ball.radius;
+                commands
+                    .entity(shield)
+                    .insert(Shield {
+                        owner: picked.player,
+                    })
+                    .insert(Transform {
+                        translation: arena_info.0.side_middle(player.side) + offset.extend(0.0),
+                        rotation: Quat::from_rotation_z(player.side.angle().to_radians()),
+                        ..Default::default()
+                    })
+                    .insert(DrawMode::Fill(
+                        bevy_prototype_lyon::prelude::FillMode::color(
+                            *player_color(player.side).set_a(0.5),
+                        ),
+                    ));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn power_up_expiry_system(
+    mut commands: Commands,
+    mut player_query: Query<(
+        Entity,
+        &mut PowerUpEffects,
+        &mut PlayerRadius,
+        &mut Collider,
+        &mut Path,
+    )>,
+    shield_query: Query<(Entity, &Shield)>,
+    rules: Res<GameRules>,
+) {
+    for (player_id, mut effects, mut radius, mut collider, mut path) in player_query.iter_mut() {
+        for kind in effects.tick(Duration::from_secs_f32(TIME_STEP)) {
+            match kind {
+                PowerUpKind::WidePaddle => {
+                    resize_player(rules.player.radius, &mut radius, &mut collider, &mut path)
+                }
+                PowerUpKind::Shield => {
+                    for (shield_id, shield) in shield_query.iter() {
+                        if shield.owner == player_id {
+                            commands.entity(shield_id).despawn();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Slow the opponents' balls down and bend the balls towards the magnets
+fn power_up_ball_system(
+    player_query: Query<(Entity, &Player, &Transform, &PowerUpEffects)>,
+    mut ball_query: Query<(&mut BallState, &Transform, &mut Velocity), FreeBallFilter>,
+    rules: Res<GameRules>,
+) {
+    let rules = &rules.power_ups;
+    let max_turn = (rules.magnet_strength * TIME_STEP).to_radians();
+
+    for (mut ball_state, ball_tf, mut velocity) in ball_query.iter_mut() {
+        let last_touch = ball_state.last_touch;
+        let slowed = player_query.iter().any(|(player_id, _, _, effects)| {
+            effects.has(PowerUpKind::SlowMotion)
+                && last_touch.map_or(false, |toucher| toucher != player_id)
+        });
+        ball_state.slowdown = if slowed {
+            1.0 - rules.slow_motion_factor
+        } else {
+            0.0
+        };
+
+        for (_, player, player_tf, effects) in player_query.iter() {
+            if !effects.has(PowerUpKind::Magnet) {
+                continue;
+            }
+
+            // Only the balls coming towards the player
+            let to_player = (player_tf.translation - ball_tf.translation).truncate();
+            if to_player.length() > rules.magnet_range
+                || velocity.linvel.dot(player.side.normal()) <= 0.0
+            {
+                continue;
+            }
+
+            let angle = velocity.linvel.angle_between(to_player);
+            if angle.is_finite() {
+                let turn = clamp(angle, -max_turn, max_turn);
+                velocity.linvel = Vec2::from_angle(turn).rotate(velocity.linvel);
+            }
+        }
+    }
+}
+
+/// Catch the balls hitting a player under the magnet effect, one at a time
+fn power_up_catch_system(
+    mut commands: Commands,
+    mut player_hits: EventReader<BallHitPlayer>,
+    player_query: Query<(&Player, &PlayerRadius, &PowerUpEffects, &Transform)>,
+    ball_query: Query<(&BallKind, &Transform, &Velocity), FreeBallFilter>,
+    caught_query: Query<&Caught>,
+    rules: Res<GameRules>,
+) {
+    let mut holders: Vec<Entity> = caught_query.iter().map(|caught| caught.player).collect();
+
+    for hit in player_hits.iter() {
+        let (player, radius, effects, player_tf) = match player_query.get(hit.player) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let (kind, ball_tf, velocity) = match ball_query.get(hit.ball) {
+            Ok(ball) => ball,
+            Err(_) => continue,
+        };
+        if !effects.has(PowerUpKind::Magnet) || holders.contains(&hit.player) {
+            continue;
+        }
+        holders.push(hit.player);
+
+        let reach = radius.0 + kind.radius(&rules.ball);
+        let offset = (ball_tf.translation - player_tf.translation)
+            .truncate()
+            .dot(player.side.tangent())
+            / reach;
+        commands.entity(hit.ball).insert(Caught {
+            player: hit.player,
+            side: player.side,
+            offset: clamp(offset, -1.0, 1.0),
+            speed: velocity.linvel.length(),
+            timer: Timer::from_seconds(rules.power_ups.catch_time, false),
+        });
+        info!("{:?} caught a ball", player.side);
+    }
+}
+
+/// Carry the caught balls along with their player, and throw them back when
+/// the player presses energy, the catch time runs out or the magnet ends
+fn power_up_hold_system(
+    mut commands: Commands,
+    mut ball_query: Query<
+        (
+            Entity,
+            &mut Caught,
+            &BallKind,
+            &mut Transform,
+            &mut Velocity,
+        ),
+        With<Ball>,
+    >,
+    player_query: Query<Holder, Without<Ball>>,
+    rules: Res<GameRules>,
+) {
+    for (ball_id, mut caught, kind, mut ball_tf, mut velocity) in ball_query.iter_mut() {
+        let finished = caught
+            .timer
+            .tick(Duration::from_secs_f32(TIME_STEP))
+            .finished();
+
+        // An eliminated player lets its ball go
+        let (radius, player_velocity, effects, action_state, player_tf) =
+            match player_query.get(caught.player) {
+                Ok(player) => player,
+                Err(_) => {
+                    velocity.linvel = deflect(
+                        caught.side,
+                        caught.offset,
+                        0.0,
+                        caught.speed,
+                        &rules.deflection,
+                    );
+                    commands.entity(ball_id).remove::<Caught>();
+                    continue;
+                }
+            };
+
+        if finished
+            || !effects.has(PowerUpKind::Magnet)
+            || action_state.just_pressed(PlayerAction::Energy)
+        {
+            velocity.linvel = deflect(
+                caught.side,
+                caught.offset,
+                player_velocity.0,
+                caught.speed,
+                &rules.deflection,
+            );
+            commands.entity(ball_id).remove::<Caught>();
+            continue;
+        }
+
+        let direction = deflect(caught.side, caught.offset, 0.0, 1.0, &rules.deflection);
+        let reach = radius.0 + kind.radius(&rules.ball) + CATCH_GAP;
+        let position = player_tf.translation.truncate() + direction * reach;
+        ball_tf.translation = position.extend(ball_tf.translation.z);
+        *velocity = Velocity::zero();
+    }
+}
+
+/// An eliminated player loses its shield along with its side
+fn shield_elimination_system(
+    mut commands: Commands,
+    mut eliminated_events: EventReader<PlayerEliminated>,
+    shield_query: Query<(Entity, &Barrier), With<Shield>>,
+) {
+    for eliminated in eliminated_events.iter() {
+        for (shield_id, barrier) in shield_query.iter() {
+            if barrier.side == eliminated.side {
+                commands.entity(shield_id).despawn();
+            }
+        }
+    }
+}
+
+/// Spin the pickups and outline the players with their latest effect
+fn power_up_visual_system(
+    mut power_up_query: Query<&mut Transform, With<PowerUp>>,
+    mut player_query: Query<(&Player, &PowerUpEffects, &mut DrawMode)>,
+) {
+    for mut transform in power_up_query.iter_mut() {
+        transform.rotate_z(PICKUP_SPIN * std::f32::consts::TAU * TIME_STEP);
+    }
+
+    for (player, effects, mut draw_mode) in player_query.iter_mut() {
+        let fill_mode = bevy_prototype_lyon::prelude::FillMode::color(player_color(player.side));
+        *draw_mode = match effects.latest() {
+            Some(kind) => DrawMode::Outlined {
+                fill_mode,
+                outline_mode: StrokeMode::new(kind.color(), 5.0),
+            },
+            None => DrawMode::Fill(fill_mode),
+        };
+    }
+}
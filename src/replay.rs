@@ -0,0 +1,433 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    arena::ArenaConfig,
+    game::{GameSeed, GameState, GameplayStep, MatchSetup},
+    player::{Controller, Player, PlayerAction, PlayerSetup},
+    rules::GameRules,
+    tick::TickApp,
+    util::Side,
+};
+use bevy::{app::AppExit, prelude::*};
+use leafwing_input_manager::{buttonlike::ButtonState, prelude::*};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_system_set(
+            SystemSet::on_exit(GameState::Menu)
+                .with_system(record_start_system)
+                .with_system(replay_reset_system),
+        )
+        .add_tick_system_set(
+            SystemSet::on_exit(GameState::MatchOver)
+                .with_system(record_start_system)
+                .with_system(replay_reset_system),
+        )
+        .add_tick_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(
+                    replay_input_system
+                        .after(GameplayStep::Ai)
+                        .before(GameplayStep::Player),
+                )
+                .with_system(
+                    record_input_system
+                        .after(replay_input_system)
+                        .before(GameplayStep::Player),
+                ),
+        )
+        .add_tick_system_set(
+            SystemSet::on_enter(GameState::MatchOver).with_system(record_save_system),
+        )
+        // Quitting or restarting from the pause menu ends the match too
+        .add_tick_system_set(SystemSet::on_enter(GameState::Menu).with_system(record_save_system))
+        .add_system_to_stage(CoreStage::PostUpdate, record_arena_system)
+        .add_system_to_stage(CoreStage::Last, record_exit_system);
+    }
+}
+
+const REPLAY_MAGIC: &[u8; 4] = b"CBRP";
+const REPLAY_VERSION: u8 = 4;
+
+/// Bit set in the flags of a player input when `PlayerAction::Move` is pressed,
+/// the axis value then follows the flags and changes
+fn move_flag() -> u8 {
+    1 << PlayerAction::Move.index()
+}
+
+/// Actions pressed by every player during each tick of a match, along with
+/// everything needed to play the match again
+pub struct Replay {
+    pub seed: u64,
+    pub arena: ArenaConfig,
+    pub rules: GameRules,
+    pub sides: Vec<Side>,
+    /// Inputs of each side, in the order of `sides`, for every playing tick
+    pub ticks: Vec<Vec<PlayerInput>>,
+}
+
+/// Pressed actions of a player, and the value of the `Move` axis
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct PlayerInput {
+    pub flags: u8,
+    /// Actions just pressed or just released on this tick. An action held since
+    /// before the round isn't just pressed on its first tick.
+    pub changes: u8,
+    pub move_value: f32,
+}
+
+impl PlayerInput {
+    fn capture(action_state: &ActionState<PlayerAction>) -> Self {
+        // Pausing is up to whoever watches the replay
+        let actions = || PlayerAction::variants().filter(|action| *action != PlayerAction::Pause);
+        let flags = actions()
+            .filter(|action| action_state.pressed(*action))
+            .fold(0, |flags, action| flags | 1 << action.index());
+        let changes = actions()
+            .filter(|action| {
+                action_state.just_pressed(*action) || action_state.just_released(*action)
+            })
+            .fold(0, |changes, action| changes | 1 << action.index());
+
+        PlayerInput {
+            flags,
+            changes,
+            move_value: action_state.value(PlayerAction::Move),
+        }
+    }
+
+    fn apply(&self, action_state: &mut ActionState<PlayerAction>) {
+        for action in PlayerAction::variants() {
+            let pressed = self.flags & 1 << action.index() != 0;
+            if pressed {
+                action_state.press(action);
+            } else {
+                action_state.release(action);
+            }
+
+            // As recorded rather than from the previous tick, the ticks outside
+            // of the round not being recorded
+            let mut data = action_state.action_data(action);
+            data.state = match (pressed, self.changes & 1 << action.index() != 0) {
+                (true, true) => ButtonState::JustPressed,
+                (true, false) => ButtonState::Pressed,
+                (false, true) => ButtonState::JustReleased,
+                (false, false) => ButtonState::Released,
+            };
+            action_state.set_action_data(action, data);
+        }
+
+        let mut move_data = action_state.action_data(PlayerAction::Move);
+        move_data.value = self.move_value;
+        action_state.set_action_data(PlayerAction::Move, move_data);
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Append `value` as RON text, preceded by its length
+fn write_ron<T: Serialize>(bytes: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    let text = ron::to_string(value).map_err(|err| invalid_data(&err.to_string()))?;
+    bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(text.as_bytes());
+    Ok(())
+}
+
+impl Replay {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.push(REPLAY_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        write_ron(&mut bytes, &self.arena)?;
+        write_ron(&mut bytes, &self.rules)?;
+        bytes.push(self.sides.len() as u8);
+        bytes.extend(self.sides.iter().map(|side| *side as u8));
+        bytes.extend_from_slice(&(self.ticks.len() as u32).to_le_bytes());
+
+        for tick in self.ticks.iter() {
+            for input in tick.iter() {
+                bytes.push(input.flags);
+                bytes.push(input.changes);
+                if input.flags & move_flag() != 0 {
+                    bytes.extend_from_slice(&input.move_value.to_le_bytes());
+                }
+            }
+        }
+
+        fs::File::create(path)?.write_all(&bytes)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut reader = ByteReader { bytes: &bytes };
+
+        if reader.take(4)? != REPLAY_MAGIC {
+            return Err(invalid_data("not a replay file"));
+        }
+        if reader.u8()? != REPLAY_VERSION {
+            return Err(invalid_data("unsupported replay version"));
+        }
+
+        let seed = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let arena: ArenaConfig = reader.ron()?;
+        arena.validate().map_err(|err| invalid_data(&err))?;
+        let rules: GameRules = reader.ron()?;
+        rules.validate().map_err(|err| invalid_data(&err))?;
+
+        let side_count = reader.u8()? as usize;
+        let sides = (0..side_count)
+            .map(|_| {
+                Side::ALL
+                    .get(reader.u8()? as usize)
+                    .copied()
+                    .ok_or_else(|| invalid_data("invalid side"))
+            })
+            .collect::<io::Result<Vec<Side>>>()?;
+
+        let tick_count = reader.u32()?;
+        let mut ticks = Vec::with_capacity(tick_count as usize);
+        for _ in 0..tick_count {
+            let mut tick = Vec::with_capacity(side_count);
+            for _ in 0..side_count {
+                let flags = reader.u8()?;
+                let changes = reader.u8()?;
+                let move_value = if flags & move_flag() != 0 {
+                    reader.f32()?
+                } else {
+                    0.0
+                };
+                tick.push(PlayerInput {
+                    flags,
+                    changes,
+                    move_value,
+                });
+            }
+            ticks.push(tick);
+        }
+
+        Ok(Replay {
+            seed,
+            arena,
+            rules,
+            sides,
+            ticks,
+        })
+    }
+
+    /// Setup of the recorded match, every player being driven by the replay
+    pub fn match_setup(&self) -> MatchSetup {
+        MatchSetup {
+            players: self
+                .sides
+                .iter()
+                .map(|side| PlayerSetup {
+                    side: *side,
+                    controller: Controller::Replay,
+                })
+                .collect(),
+        }
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            return Err(invalid_data("truncated replay file"));
+        }
+        let (head, tail) = self.bytes.split_at(count);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn ron<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        let len = self.u32()? as usize;
+        ron::de::from_bytes(self.take(len)?).map_err(|err| invalid_data(&err.to_string()))
+    }
+}
+
+/// Records the match being played into `path`
+pub struct ReplayRecorder {
+    pub path: PathBuf,
+    replay: Option<Replay>,
+}
+
+impl ReplayRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, replay: None }
+    }
+}
+
+/// Plays a recorded match back
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    tick: usize,
+}
+
+/// Play `replay` back instead of the configured match, must be called before
+/// adding the gameplay plugins so that the recorded seed, arena and rules are
+/// used
+pub fn insert_replay(app: &mut App, replay: Replay) {
+    app.insert_resource(GameSeed(replay.seed))
+        .insert_resource(replay.arena.clone())
+        .insert_resource(replay.rules.clone())
+        .insert_resource(replay.match_setup())
+        .insert_resource(ReplayPlayer { replay, tick: 0 });
+}
+
+fn replay_reset_system(replay_player: Option<ResMut<ReplayPlayer>>) {
+    if let Some(mut replay_player) = replay_player {
+        replay_player.tick = 0;
+    }
+}
+
+fn replay_input_system(
+    replay_player: Option<ResMut<ReplayPlayer>>,
+    mut player_query: Query<(&Player, &mut ActionState<PlayerAction>)>,
+) {
+    let mut replay_player = match replay_player {
+        Some(replay_player) => replay_player,
+        None => return,
+    };
+
+    let tick = replay_player.tick;
+    let inputs = match replay_player.replay.ticks.get(tick) {
+        Some(inputs) => inputs,
+        None => return,
+    };
+
+    for (player, mut action_state) in player_query.iter_mut() {
+        if let Some(idx) = replay_player
+            .replay
+            .sides
+            .iter()
+            .position(|side| *side == player.side)
+        {
+            inputs[idx].apply(&mut action_state);
+        }
+    }
+
+    replay_player.tick += 1;
+}
+
+fn record_start_system(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    seed: Res<GameSeed>,
+    arena_config: Res<ArenaConfig>,
+    rules: Res<GameRules>,
+    match_setup: Res<MatchSetup>,
+) {
+    if let Some(mut recorder) = recorder {
+        recorder.replay = Some(Replay {
+            seed: seed.0,
+            arena: arena_config.clone(),
+            rules: rules.clone(),
+            sides: match_setup
+                .players
+                .iter()
+                .map(|player| player.side)
+                .collect(),
+            ticks: Vec::new(),
+        });
+    }
+}
+
+fn record_input_system(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    player_query: Query<(&Player, &ActionState<PlayerAction>)>,
+) {
+    let replay = match recorder {
+        Some(recorder) => recorder.into_inner().replay.as_mut(),
+        None => return,
+    };
+
+    if let Some(replay) = replay {
+        // Eliminated players are recorded as not pressing anything
+        let tick = replay
+            .sides
+            .iter()
+            .map(|side| {
+                player_query
+                    .iter()
+                    .find(|(player, _)| player.side == *side)
+                    .map_or_else(PlayerInput::default, |(_, action_state)| {
+                        PlayerInput::capture(action_state)
+                    })
+            })
+            .collect();
+        replay.ticks.push(tick);
+    }
+}
+
+/// Save what was recorded of the match, and stop recording
+fn save_recording(recorder: &mut ReplayRecorder) {
+    if let Some(replay) = recorder.replay.take() {
+        match replay.save(&recorder.path) {
+            Ok(()) => info!("Replay saved to {}", recorder.path.display()),
+            Err(err) => error!(
+                "Failed to save replay to {}: {}",
+                recorder.path.display(),
+                err
+            ),
+        }
+    }
+}
+
+fn record_save_system(recorder: Option<ResMut<ReplayRecorder>>) {
+    if let Some(mut recorder) = recorder {
+        save_recording(&mut recorder);
+    }
+}
+
+/// The recording can't follow the match into an arena reloaded from disk, the
+/// inputs recorded so far are saved instead
+fn record_arena_system(recorder: Option<ResMut<ReplayRecorder>>, arena_config: Res<ArenaConfig>) {
+    let mut recorder = match recorder {
+        Some(recorder) if arena_config.is_changed() => recorder,
+        _ => return,
+    };
+
+    match recorder.replay.as_mut() {
+        Some(replay) if replay.ticks.is_empty() => replay.arena = arena_config.clone(),
+        Some(_) => save_recording(&mut recorder),
+        None => {}
+    }
+}
+
+/// Closing the window or leaving the app in any other way saves the match
+fn record_exit_system(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+    if let Some(mut recorder) = recorder {
+        save_recording(&mut recorder);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::util::{list_assets, load_ron_asset};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_RULES_PRESET: &str = "classic";
+const RULES_EXTENSION: &str = ".rules.ron";
+
+/// Tuning of the balls, players and match, read from a `.rules.ron` file of
+/// the assets
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameRules {
+    pub ball: BallRules,
+    pub player: PlayerRules,
+    pub energy: EnergyRules,
+    pub deflection: DeflectionRules,
+    pub power_ups: PowerUpRules,
+    pub pacing: PacingRules,
+    /// Rounds a player has to win to take the match
+    pub rounds_to_win: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BallRules {
+    /// Balls in the arena at the same time
+    pub max_count: u32,
+    /// Balls allowed in the arena when a round starts, one more being allowed
+    /// every `wave_interval` seconds up to `max_count`
+    pub initial_count: u32,
+    /// Seconds between two increases of the ball count, 0 allows `max_count`
+    /// balls from the start
+    pub wave_interval: f32,
+    pub speed: f32,
+    pub radius: f32,
+    /// Seconds between two spawns
+    pub spawn_interval: f32,
+    /// Maximum angle (in degrees) between a spawned ball and the arena diagonal
+    pub spawn_spread: f32,
+    /// Seconds a ball is announced at its corner before coming out
+    pub spawn_lead_time: f32,
+    /// Speed of a freshly energized ball relative to `speed`
+    pub energized_speed_factor: f32,
+    /// Seconds for the energy of a ball to fade out
+    pub energy_duration: f32,
+    /// Bounces on corners and barriers after which a ball loses its energy
+    pub energy_bounces: u32,
+    /// Smallest angle (in degrees) a ball can make with a barrier, so it does
+    /// not slide along it forever
+    pub min_wall_angle: f32,
+    /// Bounces between the same two walls, with no player in between, after
+    /// which a ball is considered stuck in a loop and turned by `min_wall_angle`
+    pub loop_bounces: u32,
+    pub kinds: BallKindRules,
+}
+
+impl BallRules {
+    /// Radius of the largest kind of ball
+    pub fn max_radius(&self) -> f32 {
+        self.radius
+            * self
+                .kinds
+                .heavy_radius_factor
+                .max(self.kinds.small_radius_factor)
+                .max(1.0)
+    }
+}
+
+/// Special balls, each kind being spawned with a chance proportional to its
+/// weight
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BallKindRules {
+    pub normal_weight: u32,
+    pub heavy_weight: u32,
+    pub small_fast_weight: u32,
+    pub explosive_weight: u32,
+    pub curving_weight: u32,
+    pub ghost_weight: u32,
+    /// Speed of a heavy ball relative to `BallRules::speed`
+    pub heavy_speed_factor: f32,
+    /// Radius of a heavy ball relative to `BallRules::radius`
+    pub heavy_radius_factor: f32,
+    /// Density of a heavy ball, other balls have a density of 1
+    pub heavy_density: f32,
+    pub small_speed_factor: f32,
+    pub small_radius_factor: f32,
+    /// Balls added when an explosive ball hits a player
+    pub explosive_count: u32,
+    /// Degrees per second a curving ball turns
+    pub curve_rate: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerRules {
+    pub speed: f32,
+    pub accelerate_speed: f32,
+    pub radius: f32,
+    /// Reach of the energy ring, on top of `radius`
+    pub energy_radius: f32,
+    /// Goals a player can concede before being eliminated
+    pub lives: u32,
+}
+
+/// Energy meter of the players and charged pulses
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EnergyRules {
+    /// Size of the energy meter, full when a round starts
+    pub capacity: f32,
+    /// Energy regained per second
+    pub regen: f32,
+    /// Energy spent by a pulse, a pulse cannot be charged with less
+    pub pulse_cost: f32,
+    /// Seconds after a pulse is released before the next one can be charged,
+    /// running while the pulse expands
+    pub cooldown: f32,
+    /// Seconds of holding the energy action to reach a full charge
+    pub charge_time: f32,
+    /// Reach of a fully charged pulse relative to `PlayerRules::energy_radius`
+    pub charged_radius_factor: f32,
+    /// Speed boost of a fully charged pulse relative to a tap
+    pub charged_speed_factor: f32,
+}
+
+/// How a ball bounces off a player
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeflectionModel {
+    /// Plain physical bounce computed by Rapier
+    Physics,
+    /// The further from the player centre the ball hits, the wider the angle
+    Classic,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeflectionRules {
+    pub model: DeflectionModel,
+    /// Angle (in degrees) from the side normal of a ball hitting the edge of
+    /// a player, with the classic model
+    pub max_angle: f32,
+    /// Part of the player velocity passed on to the ball, with the classic model
+    pub english: f32,
+}
+
+/// Pickups spawned in the arena, granting an effect to the last player who
+/// touched the ball hitting them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PowerUpRules {
+    /// Pickups in the arena at the same time, none are spawned with 0
+    pub max_count: u32,
+    /// Seconds between two spawns
+    pub spawn_interval: f32,
+    pub radius: f32,
+    /// Seconds an effect lasts, picking it again restarts it
+    pub duration: f32,
+    /// Radius of a player with a wider paddle relative to `PlayerRules::radius`
+    pub wide_paddle_factor: f32,
+    /// Balls added when a ball splits
+    pub multi_ball_count: u32,
+    /// Speed of the opponents' balls relative to their normal speed
+    pub slow_motion_factor: f32,
+    /// Distance from a player under which its magnet pulls the balls
+    pub magnet_range: f32,
+    /// Degrees per second a magnet turns a ball towards the player
+    pub magnet_strength: f32,
+    /// Seconds a magnet holds a caught ball before throwing it back
+    pub catch_time: f32,
+}
+
+/// What the pacing curve of a round is read against
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PacingDriver {
+    /// Seconds played in the round
+    RoundTime,
+    /// Goals conceded by all players in the round
+    GoalsConceded,
+}
+
+/// Pace of a round once the driver reaches `at`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PacingPoint {
+    pub at: f32,
+    /// Balls allowed on top of the ball count
+    pub extra_balls: u32,
+    /// Spawn frequency relative to `BallRules::spawn_interval`
+    pub spawn_rate: f32,
+    /// Ball speed relative to `BallRules::speed`
+    pub speed: f32,
+}
+
+/// How a round speeds up so that it does not stall
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PacingRules {
+    pub driver: PacingDriver,
+    /// Points by increasing `at`, the pace is interpolated between them and
+    /// held after the last one
+    pub curve: Vec<PacingPoint>,
+    /// Value of the driver at which sudden death starts, leaving every player
+    /// a single life
+    pub sudden_death_at: Option<f32>,
+}
+
+impl PacingRules {
+    /// Order the points of the curve by `at`, as a preset may list them in
+    /// any order
+    pub fn sort_curve(&mut self) {
+        self.curve.sort_by(|a, b| a.at.total_cmp(&b.at));
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for point in &self.curve {
+            if !point.at.is_finite() {
+                return Err(format!("pacing.curve at must be finite, not {}", point.at));
+            }
+            if !(point.spawn_rate >= 0.0 && point.spawn_rate.is_finite()) {
+                return Err(format!(
+                    "pacing.curve spawn_rate at {} must not be negative, not {}",
+                    point.at, point.spawn_rate
+                ));
+            }
+            if !(point.speed > 0.0 && point.speed.is_finite()) {
+                return Err(format!(
+                    "pacing.curve speed at {} must be positive, not {}",
+                    point.at, point.speed
+                ));
+            }
+        }
+        match self.sudden_death_at {
+            Some(at) if !at.is_finite() => {
+                Err(format!("pacing.sudden_death_at must be finite, not {}", at))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        let mut rules: Self = ron::de::from_str(include_str!("../assets/rules/classic.rules.ron"))
+            .expect("invalid classic rules preset");
+        rules.pacing.sort_curve();
+        rules.validate().expect("invalid classic rules preset");
+        rules
+    }
+}
+
+/// Preset or file the rules were read from, as given to `GameRules::load`
+pub struct RulesPreset(pub String);
+
+impl GameRules {
+    /// Read the rules of a preset of `assets/rules`, or of any `.ron` file of
+    /// the assets
+    pub fn load(preset: &str) -> Result<Self, String> {
+        let mut rules: Self = if preset.ends_with(".ron") {
+            load_ron_asset(preset)?
+        } else {
+            load_ron_asset(&format!("rules/{}{}", preset, RULES_EXTENSION))?
+        };
+        rules.pacing.sort_curve();
+        rules
+            .validate()
+            .map_err(|err| format!("invalid rules {}: {}", preset, err))?;
+        Ok(rules)
+    }
+
+    /// Check that a match can be played with the rules, so that a bad preset
+    /// fails to load rather than in the middle of a match. Intervals, lead
+    /// times and cooldowns may be 0 where their documentation allows it
+    pub fn validate(&self) -> Result<(), String> {
+        let ball = &self.ball;
+        let kinds = &ball.kinds;
+        let power_ups = &self.power_ups;
+        let positive = [
+            ("ball.speed", ball.speed),
+            ("ball.radius", ball.radius),
+            ("ball.spawn_interval", ball.spawn_interval),
+            ("ball.energized_speed_factor", ball.energized_speed_factor),
+            ("ball.energy_duration", ball.energy_duration),
+            ("ball.kinds.heavy_speed_factor", kinds.heavy_speed_factor),
+            ("ball.kinds.heavy_radius_factor", kinds.heavy_radius_factor),
+            ("ball.kinds.heavy_density", kinds.heavy_density),
+            ("ball.kinds.small_speed_factor", kinds.small_speed_factor),
+            ("ball.kinds.small_radius_factor", kinds.small_radius_factor),
+            ("player.speed", self.player.speed),
+            ("player.accelerate_speed", self.player.accelerate_speed),
+            ("player.radius", self.player.radius),
+            ("energy.capacity", self.energy.capacity),
+            ("energy.charge_time", self.energy.charge_time),
+            (
+                "energy.charged_radius_factor",
+                self.energy.charged_radius_factor,
+            ),
+            (
+                "energy.charged_speed_factor",
+                self.energy.charged_speed_factor,
+            ),
+            ("power_ups.spawn_interval", power_ups.spawn_interval),
+            ("power_ups.radius", power_ups.radius),
+            ("power_ups.duration", power_ups.duration),
+            ("power_ups.wide_paddle_factor", power_ups.wide_paddle_factor),
+            ("power_ups.slow_motion_factor", power_ups.slow_motion_factor),
+            ("power_ups.catch_time", power_ups.catch_time),
+        ];
+        let non_negative = [
+            ("ball.wave_interval", ball.wave_interval),
+            ("ball.spawn_spread", ball.spawn_spread),
+            ("ball.spawn_lead_time", ball.spawn_lead_time),
+            ("ball.min_wall_angle", ball.min_wall_angle),
+            ("player.energy_radius", self.player.energy_radius),
+            ("energy.regen", self.energy.regen),
+            ("energy.pulse_cost", self.energy.pulse_cost),
+            ("energy.cooldown", self.energy.cooldown),
+            ("deflection.max_angle", self.deflection.max_angle),
+            ("power_ups.magnet_range", power_ups.magnet_range),
+            ("power_ups.magnet_strength", power_ups.magnet_strength),
+        ];
+        let finite = [
+            ("ball.kinds.curve_rate", kinds.curve_rate),
+            ("deflection.english", self.deflection.english),
+        ];
+
+        for (name, value) in finite.iter().chain(&non_negative).chain(&positive) {
+            if !value.is_finite() {
+                return Err(format!("{} must be a finite number, not {}", name, value));
+            }
+        }
+        for (name, value) in non_negative {
+            if value < 0.0 {
+                return Err(format!("{} must not be negative, not {}", name, value));
+            }
+        }
+        for (name, value) in positive {
+            if value <= 0.0 {
+                return Err(format!("{} must be positive, not {}", name, value));
+            }
+        }
+        self.pacing.validate()
+    }
+
+    /// Names of the presets of `assets/rules`
+    pub fn presets() -> Vec<String> {
+        list_assets("rules", RULES_EXTENSION)
+            .iter()
+            .filter_map(|file| file.strip_prefix("rules/")?.strip_suffix(RULES_EXTENSION))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        let presets = GameRules::presets();
+        assert!(!presets.is_empty());
+        for preset in presets {
+            if let Err(err) = GameRules::load(&preset) {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_spawn_interval_is_rejected() {
+        let mut rules = GameRules::default();
+        rules.ball.spawn_interval = 0.0;
+        assert!(rules.validate().is_err());
+
+        let mut rules = GameRules::default();
+        rules.power_ups.spawn_interval = 0.0;
+        assert!(rules.validate().is_err());
+    }
+
+    #[test]
+    fn stopping_pacing_speed_is_rejected() {
+        let mut rules = GameRules::default();
+        rules.pacing.curve[0].speed = 0.0;
+        assert!(rules.validate().is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use crate::{
+    ball::{Ball, BallState},
+    game::{GameState, GameplayStep, RoundEntity, RoundSetup},
+    player::PlayerEliminated,
+    rules::GameRules,
+    tick::TickApp,
+    util::Side,
+    ArenaInfo, GoalSides,
+};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_event::<GoalScored>()
+            .insert_resource(Score::default())
+            .add_tick_system_set(
+                SystemSet::on_enter(GameState::Countdown)
+                    .with_system(
+                        goal_zone_spawn_system
+                            .label(RoundSetup::GoalZones)
+                            .after(RoundSetup::Barriers),
+                    )
+                    .with_system(score_reset_system),
+            )
+            .add_tick_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        goal_detection_system
+                            .label(GameplayStep::Score)
+                            .after(GameplayStep::PowerUp),
+                    )
+                    .with_system(
+                        score_update_system
+                            .label(GameplayStep::Score)
+                            .after(goal_detection_system),
+                    )
+                    .with_system(
+                        goal_zone_elimination_system
+                            .label(GameplayStep::Score)
+                            .after(score_update_system),
+                    ),
+            );
+    }
+}
+
+const GOAL_ZONE_DEPTH: f32 = 20.0;
+
+/// Sensor laid behind an open side of the arena
+#[derive(Component)]
+pub struct GoalZone {
+    pub side: Side,
+}
+
+/// Sent when a ball leaves the arena through the goal zone of `side`
+pub struct GoalScored {
+    pub side: Side,
+    pub ball: Entity,
+    /// Player whose energy was still carrying the ball, own goals excluded
+    pub scorer: Option<Side>,
+}
+
+/// Goals conceded and scored by the owner of each side
+#[derive(Default)]
+pub struct Score {
+    goals_against: HashMap<Side, u32>,
+    goals_for: HashMap<Side, u32>,
+}
+
+impl Score {
+    pub fn goals_against(&self, side: Side) -> u32 {
+        self.goals_against.get(&side).copied().unwrap_or(0)
+    }
+
+    pub fn goals_for(&self, side: Side) -> u32 {
+        self.goals_for.get(&side).copied().unwrap_or(0)
+    }
+
+    /// Goals conceded by all players
+    pub fn total_goals(&self) -> u32 {
+        self.goals_against.values().sum()
+    }
+}
+
+fn goal_zone_spawn_system(
+    mut commands: Commands,
+    arena_info: Res<ArenaInfo>,
+    goal_sides: Res<GoalSides>,
+    rules: Res<GameRules>,
+) {
+    for side in goal_sides.0.iter().copied() {
+        // The zone starts one ball diameter behind the side, so a ball only
+        // triggers it once it has fully crossed the line
+        let offset = side.normal() * (2.0 * rules.ball.max_radius() + GOAL_ZONE_DEPTH / 2.0);
+
+        commands
+            .spawn()
+            .insert(GoalZone { side })
+            .insert(RoundEntity)
+            .insert_bundle(TransformBundle::from(Transform {
+                translation: arena_info.0.side_middle(side) + offset.extend(0.0),
+                rotation: Quat::from_rotation_z(side.angle() / 180.0 * PI),
+                ..Default::default()
+            }))
+            .insert(Collider::cuboid(
+                arena_info.0.side_length(side) / 2.0,
+                GOAL_ZONE_DEPTH / 2.0,
+            ))
+            .insert(Sensor);
+    }
+}
+
+fn score_reset_system(mut score: ResMut<Score>) {
+    *score = Score::default();
+}
+
+fn goal_detection_system(
+    mut collision_events: EventReader<CollisionEvent>,
+    mut goal_events: EventWriter<GoalScored>,
+    goal_query: Query<&GoalZone>,
+    ball_query: Query<&BallState, With<Ball>>,
+) {
+    for event in collision_events.iter() {
+        if let CollisionEvent::Started(a, b, _) = event {
+            for (goal_id, ball_id) in [(a, b), (b, a)] {
+                if let Ok(goal_zone) = goal_query.get(*goal_id) {
+                    if let Ok(ball_state) = ball_query.get(*ball_id) {
+                        goal_events.send(GoalScored {
+                            side: goal_zone.side,
+                            ball: *ball_id,
+                            scorer: ball_state
+                                .owner_side()
+                                .filter(|scorer| *scorer != goal_zone.side),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn score_update_system(mut score: ResMut<Score>, mut goal_events: EventReader<GoalScored>) {
+    for goal in goal_events.iter() {
+        *score.goals_against.entry(goal.side).or_insert(0) += 1;
+        if let Some(scorer) = goal.scorer {
+            *score.goals_for.entry(scorer).or_insert(0) += 1;
+        }
+        info!(
+            "Goal against {:?} ({} conceded), scored by {:?}",
+            goal.side,
+            score.goals_against(goal.side),
+            goal.scorer
+        );
+    }
+}
+
+/// An eliminated side is walled off, its goal zone is no longer needed
+fn goal_zone_elimination_system(
+    mut commands: Commands,
+    mut eliminated_events: EventReader<PlayerEliminated>,
+    goal_query: Query<(Entity, &GoalZone)>,
+) {
+    for eliminated in eliminated_events.iter() {
+        for (goal_id, goal_zone) in goal_query.iter() {
+            if goal_zone.side == eliminated.side {
+                commands.entity(goal_id).despawn();
+            }
+        }
+    }
+}
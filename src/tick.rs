@@ -0,0 +1,196 @@
+use crate::TIME_STEP;
+use bevy::{
+    ecs::{event::Event, schedule::StateData},
+    prelude::*,
+    time::FixedTimestep,
+};
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::{
+    prelude::*,
+    systems::{tick_action_state, update_action_state},
+};
+
+/// Schedule running the game rules and the physics step together, once per
+/// tick of `TIME_STEP`. Frames only draw the outcome of the last tick.
+pub struct TickPlugin;
+
+impl Plugin for TickPlugin {
+    fn build(&self, app: &mut App) {
+        let physics_stage = |stage: PhysicsStages| {
+            SystemStage::parallel()
+                .with_system_set(RapierPhysicsPlugin::<NoUserData>::get_systems(stage))
+        };
+
+        app.add_stage_after(
+            CoreStage::Update,
+            GameTick,
+            Schedule::default()
+                .with_stage(TickStage::Input, SystemStage::parallel())
+                .with_stage(TickStage::Update, SystemStage::parallel())
+                .with_stage(
+                    PhysicsStages::SyncBackend,
+                    physics_stage(PhysicsStages::SyncBackend),
+                )
+                .with_stage(
+                    PhysicsStages::StepSimulation,
+                    physics_stage(PhysicsStages::StepSimulation),
+                )
+                .with_stage(
+                    PhysicsStages::Writeback,
+                    physics_stage(PhysicsStages::Writeback),
+                ),
+        )
+        // Despawned bodies are only reported until the end of the frame, which
+        // may have no tick left
+        .add_stage_before(
+            CoreStage::Last,
+            PhysicsStages::DetectDespawn,
+            physics_stage(PhysicsStages::DetectDespawn),
+        )
+        // Left to the app by Rapier, and read on the next tick
+        .add_tick_event::<CollisionEvent>()
+        .add_tick_event::<ContactForceEvent>();
+    }
+}
+
+/// Ticks every `TIME_STEP` of real time, as many per frame as needed to keep
+/// up, instead of once per update as in the headless simulation
+pub struct RealTimeTickPlugin;
+
+impl Plugin for RealTimeTickPlugin {
+    fn build(&self, app: &mut App) {
+        app.stage(GameTick, |schedule: &mut Schedule| {
+            schedule.set_run_criteria(FixedTimestep::step(TIME_STEP as f64))
+        });
+    }
+}
+
+/// Stage of the app holding the tick schedule
+#[derive(StageLabel, Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub struct GameTick;
+
+/// Stages of a tick, followed by the physics ones
+#[derive(StageLabel, Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub enum TickStage {
+    /// Events and actions are updated
+    Input,
+    /// The game state changes and the gameplay systems run
+    Update,
+}
+
+/// Counterparts of the `App` methods for the tick schedule
+pub trait TickApp {
+    /// Run `system_set` on every tick, in `TickStage::Update`
+    fn add_tick_system_set(&mut self, system_set: SystemSet) -> &mut Self;
+
+    /// Like `App::add_state`, the state only changing during a tick
+    fn add_tick_state<T: StateData>(&mut self, initial: T) -> &mut Self;
+
+    /// Like `App::add_event`, events being kept for two ticks rather than two
+    /// frames
+    fn add_tick_event<T: Event>(&mut self) -> &mut Self;
+
+    /// Like `InputManagerPlugin`, the actions being read from the inputs once
+    /// per tick, so that a press lasts until the next tick
+    fn add_tick_actions<A: Actionlike>(&mut self) -> &mut Self;
+}
+
+impl TickApp for App {
+    fn add_tick_system_set(&mut self, system_set: SystemSet) -> &mut Self {
+        self.stage(GameTick, |schedule: &mut Schedule| {
+            schedule.add_system_set_to_stage(TickStage::Update, system_set)
+        })
+    }
+
+    fn add_tick_state<T: StateData>(&mut self, initial: T) -> &mut Self {
+        self.insert_resource(State::new(initial))
+            .add_tick_system_set(State::<T>::get_driver())
+    }
+
+    fn add_tick_event<T: Event>(&mut self) -> &mut Self {
+        self.init_resource::<Events<T>>()
+            .stage(GameTick, |schedule: &mut Schedule| {
+                schedule.add_system_to_stage(TickStage::Input, Events::<T>::update_system)
+            })
+    }
+
+    fn add_tick_actions<A: Actionlike>(&mut self) -> &mut Self {
+        self.init_resource::<ClashStrategy>()
+            .stage(GameTick, |schedule: &mut Schedule| {
+                schedule
+                    .add_system_to_stage(TickStage::Input, tick_action_state::<A>)
+                    .add_system_to_stage(
+                        TickStage::Input,
+                        update_action_state::<A>.after(tick_action_state::<A>),
+                    )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{
+        app::ScheduleRunnerPlugin,
+        hierarchy::HierarchyPlugin,
+        time::{FixedTimesteps, TimePlugin},
+        transform::TransformPlugin,
+    };
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct TickCount(u32);
+
+    fn tick_count_system(mut count: ResMut<TickCount>) {
+        count.0 += 1;
+    }
+
+    /// Ticks run over `seconds` of real time drawn at `frame_rate` frames per
+    /// second
+    fn ticks_at_frame_rate(frame_rate: u32, seconds: u32) -> u32 {
+        let mut app = App::new();
+        app.add_plugins_with(MinimalPlugins, |group| {
+            group
+                .disable::<TimePlugin>()
+                .disable::<ScheduleRunnerPlugin>()
+        })
+        .init_resource::<Time>()
+        .init_resource::<FixedTimesteps>()
+        .init_resource::<TickCount>()
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(TickPlugin)
+        .add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                .with_default_system_setup(false),
+        )
+        .add_plugin(RealTimeTickPlugin)
+        .add_tick_system_set(SystemSet::new().with_system(tick_count_system));
+
+        let start = app.world.resource::<Time>().startup();
+        app.world.resource_mut::<Time>().update_with_instant(start);
+        let frame_time = Duration::from_secs(1) / frame_rate;
+        for frame in 1..=frame_rate * seconds {
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + frame_time * frame);
+            app.update();
+        }
+
+        app.world.resource::<TickCount>().0
+    }
+
+    #[test]
+    fn ticks_follow_real_time_whatever_the_frame_rate() {
+        let expected = (2.0 / TIME_STEP).round() as u32;
+        for frame_rate in [30, 60, 144, 240] {
+            let ticks = ticks_at_frame_rate(frame_rate, 2);
+            assert!(
+                ticks.abs_diff(expected) <= 1,
+                "{} ticks at {} fps",
+                ticks,
+                frame_rate
+            );
+        }
+    }
+}
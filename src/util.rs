@@ -1,4 +1,10 @@
-use bevy::prelude::Vec3;
+use std::fs;
+
+use bevy::{
+    asset::FileAssetIo,
+    prelude::{Vec2, Vec3},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 pub struct Rectangle {
     pub x: f32,
@@ -8,23 +14,23 @@ pub struct Rectangle {
 }
 
 impl Rectangle {
-    pub fn left(self: &Self) -> f32 {
+    pub fn left(&self) -> f32 {
         self.x
     }
 
-    pub fn right(self: &Self) -> f32 {
+    pub fn right(&self) -> f32 {
         self.x + self.w
     }
 
-    pub fn top(self: &Self) -> f32 {
+    pub fn top(&self) -> f32 {
         self.y + self.h
     }
 
-    pub fn bottom(self: &Self) -> f32 {
+    pub fn bottom(&self) -> f32 {
         self.y
     }
 
-    pub fn top_left(self: &Self) -> Vec3 {
+    pub fn top_left(&self) -> Vec3 {
         Vec3 {
             x: self.left(),
             y: self.top(),
@@ -32,7 +38,7 @@ impl Rectangle {
         }
     }
 
-    pub fn bottom_left(self: &Self) -> Vec3 {
+    pub fn bottom_left(&self) -> Vec3 {
         Vec3 {
             x: self.left(),
             y: self.bottom(),
@@ -40,7 +46,7 @@ impl Rectangle {
         }
     }
 
-    pub fn top_right(self: &Self) -> Vec3 {
+    pub fn top_right(&self) -> Vec3 {
         Vec3 {
             x: self.right(),
             y: self.top(),
@@ -48,7 +54,7 @@ impl Rectangle {
         }
     }
 
-    pub fn bottom_right(self: &Self) -> Vec3 {
+    pub fn bottom_right(&self) -> Vec3 {
         Vec3 {
             x: self.right(),
             y: self.bottom(),
@@ -56,7 +62,7 @@ impl Rectangle {
         }
     }
 
-    pub fn top_middle(self: &Self) -> Vec3 {
+    pub fn top_middle(&self) -> Vec3 {
         Vec3 {
             x: (self.left() + self.right()) / 2.0,
             y: self.top(),
@@ -64,7 +70,7 @@ impl Rectangle {
         }
     }
 
-    pub fn bottom_middle(self: &Self) -> Vec3 {
+    pub fn bottom_middle(&self) -> Vec3 {
         Vec3 {
             x: (self.left() + self.right()) / 2.0,
             y: self.bottom(),
@@ -72,7 +78,7 @@ impl Rectangle {
         }
     }
 
-    pub fn left_middle(self: &Self) -> Vec3 {
+    pub fn left_middle(&self) -> Vec3 {
         Vec3 {
             x: self.left(),
             y: (self.top() + self.bottom()) / 2.0,
@@ -80,13 +86,95 @@ impl Rectangle {
         }
     }
 
-    pub fn right_middle(self: &Self) -> Vec3 {
+    pub fn right_middle(&self) -> Vec3 {
         Vec3 {
             x: self.right(),
             y: (self.top() + self.bottom()) / 2.0,
             z: 0.0,
         }
     }
+
+    pub fn side_middle(&self, side: Side) -> Vec3 {
+        match side {
+            Side::Bottom => self.bottom_middle(),
+            Side::Right => self.right_middle(),
+            Side::Top => self.top_middle(),
+            Side::Left => self.left_middle(),
+        }
+    }
+
+    pub fn side_length(&self, side: Side) -> f32 {
+        match side {
+            Side::Bottom | Side::Top => self.w,
+            Side::Left | Side::Right => self.h,
+        }
+    }
+}
+
+/// One of the four edges of the arena
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub enum Side {
+    Bottom,
+    Right,
+    Top,
+    Left,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::Bottom, Side::Right, Side::Top, Side::Left];
+
+    /// Unit vector pointing out of the arena through this side
+    pub fn normal(&self) -> Vec2 {
+        match self {
+            Side::Bottom => Vec2::new(0.0, -1.0),
+            Side::Right => Vec2::new(1.0, 0.0),
+            Side::Top => Vec2::new(0.0, 1.0),
+            Side::Left => Vec2::new(-1.0, 0.0),
+        }
+    }
+
+    /// Unit vector along this side, pointing to the right of a player standing
+    /// on it and facing the arena
+    pub fn tangent(&self) -> Vec2 {
+        let normal = self.normal();
+        Vec2::new(-normal.y, normal.x)
+    }
+
+    /// Rotation in degrees of a shape laid along this side (0 for the bottom side)
+    pub fn angle(&self) -> f32 {
+        match self {
+            Side::Bottom => 0.0,
+            Side::Right => 90.0,
+            Side::Top => 180.0,
+            Side::Left => -90.0,
+        }
+    }
+}
+
+/// Read a RON file, `path` being relative to the assets folder
+pub fn load_ron_asset<T: DeserializeOwned>(path: &str) -> Result<T, String> {
+    let full_path = FileAssetIo::get_base_path().join("assets").join(path);
+    let bytes = fs::read(&full_path)
+        .map_err(|err| format!("failed to read {}: {}", full_path.display(), err))?;
+
+    ron::de::from_bytes(&bytes)
+        .map_err(|err| format!("invalid file {}: {}", full_path.display(), err))
+}
+
+/// Files of an assets folder whose name ends with `extension`, sorted by name
+/// and relative to the assets folder
+pub fn list_assets(dir: &str, extension: &str) -> Vec<String> {
+    let full_path = FileAssetIo::get_base_path().join("assets").join(dir);
+    let mut files: Vec<String> = fs::read_dir(full_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(extension))
+        .map(|name| format!("{}/{}", dir, name))
+        .collect();
+    files.sort();
+    files
 }
 
 pub fn clamp<T: PartialOrd>(val: T, min: T, max: T) -> T {